@@ -0,0 +1,36 @@
+use std::cell::RefCell;
+use std::time::Instant;
+
+use regc::{GcContext, GcObject, GcTarget, GcTraceToken};
+
+struct Node<'c> {
+    next: RefCell<Option<GcObject<'c, Node<'c>>>>,
+}
+
+impl<'c> GcTarget<'c> for Node<'c> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        self.next.trace(token);
+    }
+}
+
+/// Allocates self cycles that are only freed by collections, `rounds` times.
+fn churn(count: usize, rounds: usize) {
+    let context = GcContext::new();
+    let start = Instant::now();
+    for _ in 0..rounds {
+        for _ in 0..count {
+            let node = context.alloc(Node {
+                next: RefCell::new(None),
+            });
+            *node.next.borrow_mut() = Some(node.downgrade());
+        }
+        context.gc();
+    }
+    println!("alloc churn {}x{}: {:?}", count, rounds, start.elapsed());
+}
+
+fn main() {
+    for count in [100, 1_000, 4_000] {
+        churn(count, 1_000_000 / count);
+    }
+}
@@ -0,0 +1,36 @@
+use std::cell::RefCell;
+use std::time::Instant;
+
+use regc::{GcContext, GcObject, GcTarget, GcTraceToken};
+
+struct Node<'c> {
+    children: RefCell<Vec<GcObject<'c, Node<'c>>>>,
+}
+
+impl<'c> GcTarget<'c> for Node<'c> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        self.children.trace(token);
+    }
+}
+
+fn wide(width: usize) {
+    let context = GcContext::new();
+    let root = context.alloc(Node {
+        children: RefCell::new(Vec::new()),
+    });
+    for _ in 0..width {
+        let child = context.alloc(Node {
+            children: RefCell::new(Vec::new()),
+        });
+        root.children.borrow_mut().push(child.downgrade());
+    }
+    let start = Instant::now();
+    context.gc();
+    println!("mark wide vec {}: {:?}", width, start.elapsed());
+}
+
+fn main() {
+    for width in [1_000, 100_000, 1_000_000] {
+        wide(width);
+    }
+}
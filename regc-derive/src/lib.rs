@@ -0,0 +1,207 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, GenericParam,
+    Lifetime, LifetimeParam, LitStr, Path,
+};
+
+/// Derives `GcTarget` by tracing every field.
+///
+/// The impl uses the first lifetime parameter of the type as the context lifetime.
+/// A field marked `#[trace(with = "path")]` is traced by calling
+/// `path(&field, token)` instead of its own `GcTarget` impl.
+/// Unions are rejected unless marked `#[trace(unsafe_manual)]`, which traces nothing.
+/// A type without lifetime or type parameters marked `#[trace(any)]` also overrides `as_any`,
+/// to support downcasts, type queries and type finalizers. The type must be sized.
+#[proc_macro_derive(Trace, attributes(trace))]
+pub fn derive_trace(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let (unsafe_manual, any) = parse_container_attrs(&input.attrs)?;
+    if any
+        && (input.generics.lifetimes().next().is_some()
+            || input.generics.type_params().next().is_some())
+    {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "`#[trace(any)]` needs a `'static` type without lifetime or type parameters",
+        ));
+    }
+
+    let mut generics = input.generics.clone();
+    let lifetime = match input.generics.lifetimes().next() {
+        Some(param) => param.lifetime.clone(),
+        None => {
+            let lifetime = Lifetime::new("'c", Span::call_site());
+            generics.params.insert(
+                0,
+                GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+            );
+            lifetime
+        }
+    };
+    let type_params = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+    let where_clause = generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::regc::GcTarget<#lifetime>));
+    }
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            if unsafe_manual {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "`#[trace(unsafe_manual)]` is only allowed on unions",
+                ));
+            }
+            let (pattern, traces) = trace_fields(&data.fields)?;
+            quote! {
+                let Self #pattern = self;
+                #(#traces)*
+            }
+        }
+        Data::Enum(data) => {
+            if unsafe_manual {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "`#[trace(unsafe_manual)]` is only allowed on unions",
+                ));
+            }
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let (pattern, traces) = trace_fields(&variant.fields)?;
+                    Ok(quote! {
+                        Self::#ident #pattern => {
+                            #(#traces)*
+                        }
+                    })
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            if !unsafe_manual {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "cannot derive `Trace` for a union, the active field is unknown; \
+                     add `#[trace(unsafe_manual)]` if no field holds gc handles",
+                ));
+            }
+            // The user promised that no field holds a gc handle, so nothing is traced.
+            quote! {
+                let _ = token;
+            }
+        }
+    };
+
+    let as_any = if any {
+        quote! {
+            fn as_any(&self) -> ::std::option::Option<&dyn ::std::any::Any> {
+                ::std::option::Option::Some(self)
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::regc::GcTarget<#lifetime> for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn trace(&self, token: &mut ::regc::GcTraceToken<#lifetime>) {
+                #body
+            }
+
+            #as_any
+        }
+    })
+}
+
+fn parse_container_attrs(attrs: &[Attribute]) -> syn::Result<(bool, bool)> {
+    let mut unsafe_manual = false;
+    let mut any = false;
+    for attr in attrs {
+        if !attr.path().is_ident("trace") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("unsafe_manual") {
+                unsafe_manual = true;
+                Ok(())
+            } else if meta.path.is_ident("any") {
+                any = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown trace attribute"))
+            }
+        })?;
+    }
+    Ok((unsafe_manual, any))
+}
+
+fn parse_field_attrs(attrs: &[Attribute]) -> syn::Result<Option<Path>> {
+    let mut with = None;
+    for attr in attrs {
+        if !attr.path().is_ident("trace") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("with") {
+                let path: LitStr = meta.value()?.parse()?;
+                with = Some(path.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown trace attribute"))
+            }
+        })?;
+    }
+    Ok(with)
+}
+
+fn trace_fields(fields: &Fields) -> syn::Result<(TokenStream2, Vec<TokenStream2>)> {
+    let names = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("field{}", i),
+        })
+        .collect::<Vec<_>>();
+    let pattern = match fields {
+        Fields::Named(_) => quote!({ #(#names),* }),
+        Fields::Unnamed(_) => quote!(( #(#names),* )),
+        Fields::Unit => quote!(),
+    };
+    let traces = names
+        .iter()
+        .zip(fields)
+        .map(|(name, field)| {
+            Ok(match parse_field_attrs(&field.attrs)? {
+                Some(with) => quote!(#with(#name, token);),
+                None => quote!(::regc::GcTarget::trace(#name, token);),
+            })
+        })
+        .collect::<syn::Result<_>>()?;
+    Ok((pattern, traces))
+}
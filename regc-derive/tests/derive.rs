@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use regc::{GcContext, GcObject, GcRoot, GcTraceToken, Trace};
+
+#[derive(Trace)]
+struct Node<'c> {
+    name: String,
+    next: RefCell<Option<GcObject<'c, Node<'c>>>>,
+}
+
+#[derive(Trace)]
+enum Tree<'c> {
+    Leaf(u32),
+    Branch {
+        left: GcObject<'c, Tree<'c>>,
+        right: GcObject<'c, Tree<'c>>,
+    },
+}
+
+#[derive(Trace)]
+struct Wrapper<T>(T);
+
+/// A foreign container without a `GcTarget` impl.
+struct Slots<T>(Vec<Option<T>>);
+
+fn trace_slots<'c>(slots: &Slots<GcObject<'c, Node<'c>>>, token: &mut GcTraceToken<'c>) {
+    for slot in slots.0.iter().flatten() {
+        token.accept(slot);
+    }
+}
+
+#[derive(Trace)]
+struct Table<'c> {
+    #[trace(with = "trace_slots")]
+    slots: Slots<GcObject<'c, Node<'c>>>,
+}
+
+#[derive(Trace)]
+#[trace(any)]
+struct Label(String);
+
+#[derive(Trace)]
+#[allow(dead_code)]
+struct Bytes {
+    len: usize,
+    data: [u8],
+}
+
+#[derive(Trace)]
+#[trace(unsafe_manual)]
+#[allow(dead_code)]
+union Bits {
+    int: u64,
+    float: f64,
+}
+
+#[test]
+fn test_derive_struct_cycle() {
+    let context = GcContext::new();
+    let a = context.alloc(Node {
+        name: "a".to_string(),
+        next: RefCell::new(None),
+    });
+    let b = context.alloc(Node {
+        name: "b".to_string(),
+        next: RefCell::new(Some(a.downgrade())),
+    });
+    *a.next.borrow_mut() = Some(b.downgrade());
+    drop(b);
+    context.gc();
+    let b = a.next.borrow().as_ref().unwrap().upgrade().unwrap();
+    assert_eq!(b.name, "b");
+    drop(b);
+    drop(a);
+    context.gc();
+    assert_eq!(context.estimated_heap_bytes(), 0);
+}
+
+#[test]
+fn test_derive_enum_and_generic() {
+    let context = GcContext::new();
+    let left = context.alloc(Tree::Leaf(1));
+    let right = context.alloc(Tree::Leaf(2));
+    let root = context.alloc(Wrapper(Tree::Branch {
+        left: left.downgrade(),
+        right: right.downgrade(),
+    }));
+    drop(left);
+    drop(right);
+    context.gc();
+    match &root.0 {
+        Tree::Branch { left, right } => {
+            assert!(matches!(*left.upgrade().unwrap(), Tree::Leaf(1)));
+            assert!(matches!(*right.upgrade().unwrap(), Tree::Leaf(2)));
+        }
+        Tree::Leaf(_) => unreachable!(),
+    }
+}
+
+#[test]
+fn test_derive_with() {
+    let context = GcContext::new();
+    let node = context.alloc(Node {
+        name: "a".to_string(),
+        next: RefCell::new(None),
+    });
+    let table = context.alloc(Table {
+        slots: Slots(vec![None, Some(node.downgrade())]),
+    });
+    drop(node);
+    context.gc();
+    let node = table.slots.0[1].as_ref().unwrap().upgrade().unwrap();
+    assert_eq!(node.name, "a");
+}
+
+#[test]
+fn test_derive_union() {
+    let context = GcContext::new();
+    let bits = context.alloc(Bits { int: 7 });
+    context.gc();
+    assert_eq!(unsafe { bits.int }, 7);
+}
+
+#[test]
+fn test_derive_as_any() {
+    let context = GcContext::new();
+    let _a = context.alloc(Label("a".to_string()));
+    let _node = context.alloc(Node {
+        name: "b".to_string(),
+        next: RefCell::new(None),
+    });
+    let labels = context.objects_of_type::<Label>();
+    assert_eq!(labels.len(), 1);
+    assert_eq!(labels[0].upgrade().unwrap().0, "a");
+}
+
+#[test]
+fn test_derive_downcast() {
+    let context = GcContext::new();
+    let a = context.alloc(Label("a".to_string())).cast_dyn();
+    let a = GcRoot::<String>::try_from(a).unwrap_err();
+    let a: GcRoot<Label> = a.try_into().unwrap();
+    assert_eq!(a.0, "a");
+}
+
+#[test]
+fn test_derive_type_finalizer() {
+    #[derive(Trace)]
+    struct Labeled<'c> {
+        label: GcObject<'c, Label>,
+        this: RefCell<Option<GcObject<'c, Labeled<'c>>>>,
+    }
+
+    let context = GcContext::new();
+    let finalized = Rc::new(RefCell::new(Vec::new()));
+    let f = finalized.clone();
+    context.set_type_finalizer::<Label>(Box::new(move |x| f.borrow_mut().push(x.0.clone())));
+    let a = context.alloc(Labeled {
+        label: context.alloc(Label("a".to_string())).downgrade(),
+        this: RefCell::new(None),
+    });
+    *a.this.borrow_mut() = Some(a.downgrade());
+    drop(a);
+    context.collect_to_completion_with_finalizers();
+    assert_eq!(*finalized.borrow(), ["a"]);
+}
+
+#[test]
+fn test_derive_ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
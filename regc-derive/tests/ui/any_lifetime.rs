@@ -0,0 +1,7 @@
+use regc::Trace;
+
+#[derive(Trace)]
+#[trace(any)]
+struct Foo<'c>(&'c str);
+
+fn main() {}
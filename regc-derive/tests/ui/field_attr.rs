@@ -0,0 +1,9 @@
+use regc::Trace;
+
+#[derive(Trace)]
+struct Foo {
+    #[trace(skip)]
+    bar: u32,
+}
+
+fn main() {}
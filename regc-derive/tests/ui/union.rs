@@ -0,0 +1,9 @@
+use regc::Trace;
+
+#[derive(Trace)]
+union Bits {
+    int: u64,
+    float: f64,
+}
+
+fn main() {}
@@ -0,0 +1,172 @@
+use std::cell::{BorrowError, BorrowMutError, Cell, Ref, RefCell, RefMut, UnsafeCell};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+use crate::{GcTarget, GcTraceToken};
+
+/// A mutable field that is always traced.
+///
+/// Tracing a mutably borrowed cell marks the collection incomplete instead of panicking.
+pub struct GcCell<'c, T: GcTarget<'c>> {
+    value: RefCell<T>,
+    marker: PhantomData<&'c ()>,
+}
+
+impl<'c, T: GcTarget<'c>> GcCell<'c, T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: RefCell::new(value),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.value.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.value.borrow_mut()
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.value.try_borrow()
+    }
+
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        self.value.try_borrow_mut()
+    }
+
+    pub fn set(&self, value: T) {
+        drop(self.replace(value));
+    }
+
+    pub fn replace(&self, value: T) -> T {
+        self.value.replace(value)
+    }
+
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.value.take()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<'c, T: GcTarget<'c>> GcTarget<'c> for GcCell<'c, T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        self.value.trace(token);
+    }
+}
+
+impl<'c, T: GcTarget<'c> + Default> Default for GcCell<'c, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<'c, T: GcTarget<'c> + Debug> Debug for GcCell<'c, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.value, f)
+    }
+}
+
+/// A mutable field without borrow guards.
+///
+/// Values are read by copy or inside `with`, and replaced whole. Replacing the value while it
+/// is being read or traced panics.
+pub struct GcMut<'c, T: GcTarget<'c>> {
+    value: UnsafeCell<T>,
+    readers: Cell<usize>,
+    marker: PhantomData<&'c ()>,
+}
+
+struct GcMutRead<'s>(&'s Cell<usize>);
+
+impl<'s> GcMutRead<'s> {
+    fn new(readers: &'s Cell<usize>) -> Self {
+        readers.set(readers.get() + 1);
+        Self(readers)
+    }
+}
+
+impl<'s> Drop for GcMutRead<'s> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+impl<'c, T: GcTarget<'c>> GcMut<'c, T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            readers: Cell::new(0),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn get(&self) -> T
+    where
+        T: Copy,
+    {
+        unsafe { *self.value.get() }
+    }
+
+    /// Calls `f` with a reference to the value.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let _read = GcMutRead::new(&self.readers);
+        f(unsafe { &*self.value.get() })
+    }
+
+    pub fn set(&self, value: T) {
+        drop(self.replace(value));
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the value is being read by `with` or traced.
+    pub fn replace(&self, value: T) -> T {
+        assert_eq!(self.readers.get(), 0, "replace a GcMut while it is read");
+        unsafe { std::mem::replace(&mut *self.value.get(), value) }
+    }
+
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<'c, T: GcTarget<'c>> GcTarget<'c> for GcMut<'c, T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        self.with(|value| value.trace(token));
+    }
+}
+
+impl<'c, T: GcTarget<'c> + Default> Default for GcMut<'c, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<'c, T: GcTarget<'c> + Debug> Debug for GcMut<'c, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.with(|value| f.debug_tuple("GcMut").field(value).finish())
+    }
+}
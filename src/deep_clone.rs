@@ -0,0 +1,113 @@
+use std::any::type_name;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+use crate::{GcContext, GcObject, GcRoot, GcRootThin, GcTarget};
+
+/// Copies an object graph into another context.
+///
+/// An implementation allocates the copy in `dst`, registers it with
+/// [`GcCloneMap::insert`] before copying its children, and copies every child
+/// through [`GcCloneMap::clone_object`], so that shared and cyclic structure
+/// is copied once.
+pub trait GcDeepClone<'c, 'd>: GcTarget<'c> + 'c {
+    type Target: GcTarget<'d> + 'd;
+
+    fn deep_clone(
+        this: &GcRoot<'c, Self>,
+        dst: &'d GcContext<'d>,
+        map: &mut GcCloneMap<'c, 'd>,
+    ) -> GcRoot<'d, Self::Target>;
+}
+
+/// Copies made so far by a deep clone, keyed by source object.
+///
+/// The sources and the copies are kept rooted until the map is dropped, so a source address
+/// can not be reused by another object while it is a key.
+#[derive(Default)]
+pub struct GcCloneMap<'c, 'd> {
+    map: HashMap<usize, GcCloneEntry<'c, 'd>>,
+}
+
+struct GcCloneEntry<'c, 'd> {
+    _src: GcRootThin<'c>,
+    dst: GcRootThin<'d>,
+    target: &'static str,
+}
+
+impl<'c, 'd> GcCloneMap<'c, 'd> {
+    pub fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+
+    pub fn insert<T: GcDeepClone<'c, 'd>>(
+        &mut self,
+        src: &GcRoot<'c, T>,
+        dst: &GcRoot<'d, T::Target>,
+    ) {
+        let key = src.base_ptr().as_ptr() as usize;
+        let entry = GcCloneEntry {
+            _src: src.clone().cast_thin(),
+            dst: dst.clone().cast_thin(),
+            target: type_name::<T::Target>(),
+        };
+        self.map.insert(key, entry);
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the copy of `src` was registered with another target type.
+    pub fn get<T: GcDeepClone<'c, 'd>>(
+        &self,
+        src: &GcRoot<'c, T>,
+    ) -> Option<GcRoot<'d, T::Target>> {
+        let key = src.base_ptr().as_ptr() as usize;
+        self.map.get(&key).map(|entry| {
+            // The source is rooted, so the key still names the object `T` was cloned from.
+            assert_eq!(
+                entry.target,
+                type_name::<T::Target>(),
+                "deep clone target type mismatch"
+            );
+            let r = entry.dst.clone().cast_fat();
+            let ptr = r.ptr.cast();
+            std::mem::forget(r);
+            GcRoot { ptr }
+        })
+    }
+
+    /// Returns the copy of `src`, deep cloning it first if needed.
+    ///
+    /// Returns `None` if `src` was already collected.
+    pub fn clone_object<T: GcDeepClone<'c, 'd>>(
+        &mut self,
+        src: &GcObject<'c, T>,
+        dst: &'d GcContext<'d>,
+    ) -> Option<GcRoot<'d, T::Target>> {
+        let src = src.upgrade()?;
+        if let Some(r) = self.get(&src) {
+            return Some(r);
+        }
+        let r = T::deep_clone(&src, dst, self);
+        self.insert(&src, &r);
+        Some(r)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<'c, 'd> Debug for GcCloneMap<'c, 'd> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GcCloneMap")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
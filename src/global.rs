@@ -0,0 +1,24 @@
+use crate::{GcContext, GcRoot, GcTarget};
+
+thread_local! {
+    static CONTEXT: &'static GcContext<'static> = Box::leak(Box::new(GcContext::new()));
+}
+
+/// Returns the context of the current thread, for programs that do not pass a context around.
+///
+/// The context is created on first use and never dropped, so its objects are only freed by
+/// collections and its handles stay valid until the thread exits.
+pub fn context() -> &'static GcContext<'static> {
+    CONTEXT.with(|context| *context)
+}
+
+/// Allocates `value` in the context of the current thread.
+#[track_caller]
+pub fn gc_alloc<T: GcTarget<'static> + 'static>(value: T) -> GcRoot<'static, T> {
+    context().alloc(value)
+}
+
+/// Runs a collection in the context of the current thread.
+pub fn gc_collect() {
+    context().gc();
+}
@@ -1,14 +1,39 @@
-use std::any::type_name;
-use std::cell::Cell;
-use std::fmt::{Debug, Formatter};
+use std::alloc::{alloc, handle_alloc_error, Layout};
+use std::any::{type_name, Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
-use std::mem::{forget, transmute, ManuallyDrop};
-use std::ops::Deref;
-use std::ptr::NonNull;
+use std::mem::{
+    align_of, align_of_val, forget, offset_of, replace, size_of, size_of_val, transmute,
+    ManuallyDrop, MaybeUninit,
+};
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "alloc-tracking")]
+use std::panic::Location;
+use std::pin::Pin;
+use std::ptr::{addr_of_mut, read_volatile, NonNull};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use log::{info, trace, warn};
 
+use crate::cell::GcMut;
+use crate::deep_clone::{GcCloneMap, GcDeepClone};
+use crate::snapshot::HeapSnapshot;
+
+pub mod cell;
+pub mod deep_clone;
+#[cfg(feature = "global")]
+pub mod global;
+pub mod snapshot;
 pub mod trace;
+pub mod weak_map;
+
+#[cfg(feature = "derive")]
+pub use regc_derive::Trace;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum GcState {
@@ -16,6 +41,9 @@ enum GcState {
     Dropped,
     Tracked,
     Untracked,
+    Pending,
+    /// Allocated by `alloc_cyclic_typed`, the value is not written yet.
+    Uninit,
 }
 
 struct GcInfo<'c> {
@@ -24,6 +52,33 @@ struct GcInfo<'c> {
     state: Cell<GcState>,
     root: Cell<usize>,
     count: Cell<usize>,
+    finalized: Cell<bool>,
+    #[cfg(feature = "debug-checks")]
+    context_id: Cell<usize>,
+    #[cfg(feature = "alloc-tracking")]
+    location: Cell<Option<&'static Location<'static>>>,
+    /// The context that pools the block once the object is released by reference counting.
+    #[cfg(feature = "pooling")]
+    pool: Cell<Option<NonNull<GcContextRaw<'c>>>>,
+}
+
+impl<'c> GcInfo<'c> {
+    fn new(state: GcState) -> Self {
+        Self {
+            next: Cell::new(None),
+            prev: Cell::new(None),
+            state: Cell::new(state),
+            root: Cell::new(0),
+            count: Cell::new(0),
+            finalized: Cell::new(false),
+            #[cfg(feature = "debug-checks")]
+            context_id: Cell::new(0),
+            #[cfg(feature = "alloc-tracking")]
+            location: Cell::new(None),
+            #[cfg(feature = "pooling")]
+            pool: Cell::new(None),
+        }
+    }
 }
 
 #[repr(C)]
@@ -37,13 +92,7 @@ impl<'c, T: GcTarget<'c> + 'c> GcBox<'c, T> {
     fn new(value: T) -> Self {
         let mut r = Self {
             metadata: GcBoxDynPtr::from_ptr(std::ptr::null::<GcBox<'c, T>>()).metadata,
-            info: GcInfo {
-                next: Cell::new(None),
-                prev: Cell::new(None),
-                state: Cell::new(GcState::Active),
-                root: Cell::new(0),
-                count: Cell::new(0),
-            },
+            info: GcInfo::new(GcState::Active),
             value: ManuallyDrop::new(value),
         };
         r.metadata = GcBoxDynPtr::from_ptr(&r).metadata;
@@ -55,6 +104,18 @@ impl<'c, T: GcTarget<'c> + 'c> GcBox<'c, T> {
         trace!("alloc {} {:?}", type_name::<T>(), r as *mut ());
         unsafe { NonNull::new_unchecked(r) }
     }
+
+    fn try_alloc(value: T) -> Result<NonNull<Self>, T> {
+        // The layout matches `Box<Self>`, so the box is freed by `free` as usual.
+        let r = unsafe { alloc(Layout::new::<Self>()) }.cast::<Self>();
+        let Some(r) = NonNull::new(r) else {
+            warn!("alloc {} failed", type_name::<T>());
+            return Err(value);
+        };
+        unsafe { r.as_ptr().write(Self::new(value)) };
+        trace!("alloc {} {:?}", type_name::<T>(), r.as_ptr() as *mut ());
+        Ok(r)
+    }
 }
 
 impl<'c, T: GcTarget<'c> + ?Sized> GcBox<'c, T> {
@@ -73,9 +134,30 @@ impl<'c, T: GcTarget<'c> + ?Sized> GcBox<'c, T> {
         let next = r.info.next.get();
         prev.unwrap_unchecked().as_ref().info.next.set(next);
         next.unwrap_unchecked().as_ref().info.prev.set(prev);
+        #[cfg(feature = "pooling")]
+        if let Some(context) = r.info.pool.get() {
+            context.as_ref().free_box(NonNullGcBox::from_non_null(this));
+            return;
+        }
         Self::free(this);
     }
 
+    fn is<U: Any>(&self) -> bool {
+        let value = unsafe { &*self.value() };
+        match value.as_any() {
+            Some(any) => {
+                any.is::<U>()
+                    && std::ptr::eq(
+                        any as *const dyn Any as *const (),
+                        self.value() as *const (),
+                    )
+                    && size_of_val(value) == size_of::<U>()
+                    && align_of_val(value) == align_of::<U>()
+            }
+            None => false,
+        }
+    }
+
     unsafe fn drop_value(&mut self) {
         trace!("drop {:?}", self as *mut Self as *mut ());
         ManuallyDrop::drop(&mut self.value);
@@ -92,7 +174,7 @@ impl<'c, T: GcTarget<'c> + ?Sized> GcBox<'c, T> {
                 }
             }
             GcState::Dropped => Self::remove(this),
-            GcState::Tracked | GcState::Untracked => {}
+            GcState::Tracked | GcState::Untracked | GcState::Pending | GcState::Uninit => {}
         }
     }
 }
@@ -123,7 +205,7 @@ impl<'c> GcBoxDynPtr<'c> {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
 #[repr(transparent)]
 struct NonNullGcBox<'c> {
     ptr: NonNull<()>,
@@ -167,7 +249,8 @@ impl<'c> Debug for NonNullGcBox<'c> {
     }
 }
 
-#[derive(Eq, PartialEq)]
+#[must_use = "dropping this root makes the object eligible for collection"]
+#[derive(Eq, PartialEq, Hash)]
 pub struct GcRootThin<'c> {
     ptr: NonNullGcBox<'c>,
     marker: PhantomData<GcRoot<'c, dyn GcTarget<'c> + 'c>>,
@@ -220,6 +303,11 @@ impl<'c> Clone for GcRootThin<'c> {
     }
 }
 
+/// Roots are not traced, their root count already keeps the target alive.
+///
+/// A root stored in an object is released when the object is dropped, so once the holder is
+/// collected its targets become collectible by a later pass. A root that leads back to its
+/// holder keeps both alive forever, store a `GcObjectThin` for edges that may form cycles.
 impl<'c> GcTarget<'c> for GcRootThin<'c> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
         let _ = token;
@@ -240,6 +328,7 @@ impl<'c> Deref for GcRootThin<'c> {
     }
 }
 
+#[must_use = "dropping this root makes the object eligible for collection"]
 #[derive(Eq)]
 pub struct GcRoot<'c, T: GcTarget<'c> + ?Sized + 'c> {
     ptr: NonNull<GcBox<'c, T>>,
@@ -274,6 +363,27 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> GcRoot<'c, T> {
         unsafe { GcObject::from_box(self.ptr) }
     }
 
+    /// Borrows a non-owning view of the object without touching its counts.
+    pub fn weak_ref(&self) -> WeakRef<'_, 'c, T> {
+        WeakRef {
+            ptr: self.ptr,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns `true` if both roots point to the same object, like `==`.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    /// Returns `true` if the values are equal, even if they are distinct objects.
+    pub fn value_eq(&self, other: &Self) -> bool
+    where
+        T: PartialEq,
+    {
+        **self == **other
+    }
+
     pub fn cast_dyn(self) -> GcRoot<'c, dyn GcTarget<'c> + 'c> {
         unsafe {
             let r = GcRoot {
@@ -286,6 +396,28 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> GcRoot<'c, T> {
         }
     }
 
+    /// Returns a mutable reference if this is the only handle to the object.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        unsafe {
+            let node = self.ptr.as_mut();
+            if node.info.root.get() == 1 && node.info.count.get() == 0 {
+                Some(node.value.deref_mut())
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Keeps the object rooted forever and returns a plain reference to it.
+    ///
+    /// This intentionally leaks: the object is never collected, not even when the context is
+    /// dropped, and its value is never dropped.
+    pub fn leak(self) -> &'c T {
+        let r = unsafe { &*self.as_ptr() };
+        forget(self);
+        r
+    }
+
     pub fn cast_thin(self) -> GcRootThin<'c> {
         let r = GcRootThin {
             ptr: NonNullGcBox::from_non_null(self.ptr),
@@ -296,12 +428,73 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> GcRoot<'c, T> {
     }
 }
 
+impl<'c, T: GcTarget<'c> + 'c> GcRoot<'c, T> {
+    /// Consumes the root without releasing it and returns a pointer to the value.
+    ///
+    /// The object stays rooted until the pointer is passed to `from_raw`.
+    pub fn into_raw(self) -> *const T {
+        let r = self.as_ptr();
+        forget(self);
+        r
+    }
+
+    /// Takes back a root given away by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` on a root of context `'c`, and each pointer may only
+    /// be taken back once.
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        let ptr = ptr
+            .cast::<u8>()
+            .sub(offset_of!(GcBox<'c, T>, value))
+            .cast::<GcBox<'c, T>>();
+        Self {
+            ptr: NonNull::new_unchecked(ptr.cast_mut()),
+        }
+    }
+}
+
+impl<'c> GcRoot<'c, dyn GcTarget<'c> + 'c> {
+    /// Converts to a typed root if the value is a `T`, giving the root back otherwise.
+    ///
+    /// The value is only recognized if its type overrides `GcTarget::as_any`, as
+    /// `#[derive(Trace)]` does with `#[trace(any)]`. `as_any` needs a `'static` type, so types
+    /// with a lifetime parameter, like most types holding handles, can't be downcast this way,
+    /// and a hand-written impl that keeps the default `as_any` always gives `Err`. Store such
+    /// values as `Box<dyn GcAny>` instead.
+    pub fn downcast<T: GcTarget<'c> + 'static>(self) -> Result<GcRoot<'c, T>, Self> {
+        if unsafe { self.ptr.as_ref() }.is::<T>() {
+            let r = GcRoot {
+                ptr: self.ptr.cast::<GcBox<'c, T>>(),
+            };
+            forget(self);
+            Ok(r)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Converts with [`GcRoot::downcast`], so it fails for every type that doesn't override
+/// `GcTarget::as_any`.
+impl<'c, T: GcTarget<'c> + 'static> TryFrom<GcRoot<'c, dyn GcTarget<'c> + 'c>> for GcRoot<'c, T> {
+    type Error = GcRoot<'c, dyn GcTarget<'c> + 'c>;
+
+    fn try_from(value: GcRoot<'c, dyn GcTarget<'c> + 'c>) -> Result<Self, Self::Error> {
+        value.downcast()
+    }
+}
+
 impl<'c, T: GcTarget<'c> + ?Sized + 'c> PartialEq for GcRoot<'c, T> {
     fn eq(&self, other: &Self) -> bool {
         PartialEq::eq(&self.ptr.cast::<()>(), &other.ptr.cast::<()>())
     }
-    fn ne(&self, other: &Self) -> bool {
-        PartialEq::ne(&self.ptr.cast::<()>(), &other.ptr.cast::<()>())
+}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> Hash for GcRoot<'c, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Hash::hash(&self.ptr.cast::<()>(), state)
     }
 }
 
@@ -311,6 +504,7 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> Clone for GcRoot<'c, T> {
     }
 }
 
+/// Like the `GcRootThin` impl, stored roots are released when their holder is dropped.
 impl<'c, T: GcTarget<'c> + ?Sized + 'c> GcTarget<'c> for GcRoot<'c, T> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
         let _ = token;
@@ -331,7 +525,56 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> Deref for GcRoot<'c, T> {
     }
 }
 
-#[derive(Eq, PartialEq)]
+/// A view of an object borrowed from a [`GcRoot`], valid while the root lives.
+///
+/// Unlike [`GcObject`] it is not counted, so it is free to copy down the call stack but can not
+/// be stored in the heap; convert it with `to_object` for that.
+pub struct WeakRef<'r, 'c, T: GcTarget<'c> + ?Sized + 'c> {
+    ptr: NonNull<GcBox<'c, T>>,
+    marker: PhantomData<&'r GcRoot<'c, T>>,
+}
+
+impl<'r, 'c, T: GcTarget<'c> + ?Sized + 'c> WeakRef<'r, 'c, T> {
+    pub fn base_ptr(&self) -> NonNull<()> {
+        self.ptr.cast()
+    }
+
+    pub fn get(&self) -> &'r T {
+        unsafe { &*self.ptr.as_ref().value() }
+    }
+
+    pub fn to_object(&self) -> GcObject<'c, T> {
+        unsafe { GcObject::from_box(self.ptr) }
+    }
+
+    pub fn to_root(&self) -> GcRoot<'c, T> {
+        unsafe { GcRoot::from_box(self.ptr) }
+    }
+}
+
+impl<'r, 'c, T: GcTarget<'c> + ?Sized + 'c> Clone for WeakRef<'r, 'c, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'r, 'c, T: GcTarget<'c> + ?Sized + 'c> Copy for WeakRef<'r, 'c, T> {}
+
+impl<'r, 'c, T: GcTarget<'c> + ?Sized + 'c> Debug for WeakRef<'r, 'c, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.ptr, f)
+    }
+}
+
+impl<'r, 'c, T: GcTarget<'c> + ?Sized + 'c> Deref for WeakRef<'r, 'c, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.get()
+    }
+}
+
+#[derive(Eq, PartialEq, Hash)]
 pub struct GcObjectThin<'c> {
     ptr: NonNullGcBox<'c>,
     marker: PhantomData<GcObject<'c, dyn GcTarget<'c> + 'c>>,
@@ -357,6 +600,20 @@ impl<'c> GcObjectThin<'c> {
         }
     }
 
+    /// Rebuilds a handle from an address returned by `base_ptr`, `None` if `ptr` is null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null `ptr` must be the base address of an object of context `'c` that has not
+    /// been freed.
+    pub unsafe fn from_raw(ptr: *const ()) -> Option<Self> {
+        let node = NonNullGcBox {
+            ptr: NonNull::new(ptr.cast_mut())?,
+            marker: PhantomData,
+        };
+        Some(Self::from_box(node.as_non_null()))
+    }
+
     pub fn base_ptr(&self) -> NonNull<()> {
         self.ptr.ptr
     }
@@ -365,13 +622,32 @@ impl<'c> GcObjectThin<'c> {
         unsafe { self.ptr.as_ref().value() }
     }
 
+    /// Returns the vtable pointer of the value, a cheap key for caches of per-type data.
+    ///
+    /// Equal pointers do not prove equal types and a type may have several vtables, so
+    /// confirm a cache hit with a downcast before relying on the type.
+    pub fn vtable(&self) -> *const () {
+        unsafe { self.ptr.as_ref().metadata }
+    }
+
+    /// Number of roots, a nonzero count keeps the object alive.
+    pub fn root_count(&self) -> usize {
+        unsafe { self.ptr.as_ref().info.root.get() }
+    }
+
+    /// Number of object handles, including this one.
+    pub fn weak_count(&self) -> usize {
+        unsafe { self.ptr.as_ref().info.count.get() }
+    }
+
+    #[must_use = "dropping this root makes the object eligible for collection"]
     pub fn upgrade(&self) -> Option<GcRootThin<'c>> {
         let r = unsafe { self.ptr.as_ref() };
         match r.info.state.get() {
-            GcState::Active | GcState::Tracked => unsafe {
+            GcState::Active | GcState::Tracked | GcState::Pending => unsafe {
                 Some(GcRootThin::from_box(self.ptr.as_non_null()))
             },
-            GcState::Dropped | GcState::Untracked => None,
+            GcState::Dropped | GcState::Untracked | GcState::Uninit => None,
         }
     }
 
@@ -431,14 +707,44 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> GcObject<'c, T> {
         unsafe { self.ptr.as_ref().value() }
     }
 
+    /// Number of roots, a nonzero count keeps the object alive.
+    pub fn root_count(&self) -> usize {
+        unsafe { self.ptr.as_ref().info.root.get() }
+    }
+
+    /// Number of object handles, including this one.
+    pub fn weak_count(&self) -> usize {
+        unsafe { self.ptr.as_ref().info.count.get() }
+    }
+
+    #[must_use = "dropping this root makes the object eligible for collection"]
     pub fn upgrade(&self) -> Option<GcRoot<'c, T>> {
         let r = unsafe { &*self.ptr.as_ptr() };
         match r.info.state.get() {
-            GcState::Active | GcState::Tracked => unsafe { Some(GcRoot::from_box(self.ptr)) },
-            GcState::Dropped | GcState::Untracked => None,
+            GcState::Active | GcState::Tracked | GcState::Pending => unsafe {
+                Some(GcRoot::from_box(self.ptr))
+            },
+            GcState::Dropped | GcState::Untracked | GcState::Uninit => None,
         }
     }
 
+    /// Upgrades, calls `f` with the value and drops the root again.
+    ///
+    /// Returns `None` if the object was collected.
+    pub fn upgrade_map<U>(&self, f: impl FnOnce(&T) -> U) -> Option<U> {
+        self.upgrade().map(|root| f(&root))
+    }
+
+    /// Like `upgrade`, but consumes the handle and gives it back if the object is not live.
+    pub fn try_into_root(self) -> Result<GcRoot<'c, T>, Self> {
+        self.upgrade().ok_or(self)
+    }
+
+    /// Upgrades every handle, skipping collected objects.
+    pub fn upgrade_all(handles: &[Self]) -> Vec<GcRoot<'c, T>> {
+        handles.iter().filter_map(Self::upgrade).collect()
+    }
+
     pub fn cast_dyn(self) -> GcObject<'c, dyn GcTarget<'c> + 'c> {
         let r = GcObject {
             ptr: NonNullGcBox::from_non_null(self.ptr).as_non_null(),
@@ -461,8 +767,13 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> PartialEq for GcObject<'c, T> {
     fn eq(&self, other: &Self) -> bool {
         PartialEq::eq(&self.ptr.cast::<()>(), &other.ptr.cast::<()>())
     }
-    fn ne(&self, other: &Self) -> bool {
-        PartialEq::ne(&self.ptr.cast::<()>(), &other.ptr.cast::<()>())
+}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> Eq for GcObject<'c, T> {}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> Hash for GcObject<'c, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Hash::hash(&self.ptr.cast::<()>(), state)
     }
 }
 
@@ -484,21 +795,234 @@ impl<'c, T: GcTarget<'c> + ?Sized + 'c> Debug for GcObject<'c, T> {
     }
 }
 
+/// The target of a [`GcWeak`], cleared by the collection that frees it.
+struct GcWeakSlot<'c>(Cell<Option<NonNullGcBox<'c>>>);
+
+impl<'c> Drop for GcWeakSlot<'c> {
+    fn drop(&mut self) {
+        if let Some(node) = self.0.take() {
+            unsafe {
+                let r = node.as_ref();
+                r.info.count.set(r.info.count.get() - 1);
+                GcBox::check_ref(node.as_non_null());
+            }
+        }
+    }
+}
+
+/// A handle that does not keep its target alive, and knows when it was collected.
+///
+/// Unlike a [`GcObject`] held outside the heap, a weak handle is cleared when a collection
+/// frees its target, so it can be kept anywhere. Create one with [`GcContext::downgrade_weak`].
+pub struct GcWeak<'c, T: GcTarget<'c> + ?Sized + 'c> {
+    ptr: NonNull<GcBox<'c, T>>,
+    slot: Rc<GcWeakSlot<'c>>,
+}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> GcWeak<'c, T> {
+    pub fn is_live(&self) -> bool {
+        self.slot.0.get().is_some()
+            && matches!(
+                unsafe { self.ptr.as_ref() }.info.state.get(),
+                GcState::Active | GcState::Tracked | GcState::Pending
+            )
+    }
+
+    #[must_use = "dropping this root makes the object eligible for collection"]
+    pub fn upgrade(&self) -> Option<GcRoot<'c, T>> {
+        if self.is_live() {
+            Some(unsafe { GcRoot::from_box(self.ptr) })
+        } else {
+            None
+        }
+    }
+
+    /// Like [`GcObject::upgrade_map`].
+    pub fn upgrade_map<U>(&self, f: impl FnOnce(&T) -> U) -> Option<U> {
+        self.upgrade().map(|root| f(&root))
+    }
+}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> Clone for GcWeak<'c, T> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: self.ptr,
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> Debug for GcWeak<'c, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.ptr, f)
+    }
+}
+
+/// Weak handles are not traced.
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> GcTarget<'c> for GcWeak<'c, T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        let _ = token;
+    }
+}
+
+struct GcEphemeronSlot<'c> {
+    key: Cell<Option<NonNullGcBox<'c>>>,
+    value: Cell<Option<NonNullGcBox<'c>>>,
+}
+
+impl<'c> Drop for GcEphemeronSlot<'c> {
+    fn drop(&mut self) {
+        for node in [self.key.take(), self.value.take()].into_iter().flatten() {
+            unsafe {
+                let r = node.as_ref();
+                r.info.count.set(r.info.count.get() - 1);
+                GcBox::check_ref(node.as_non_null());
+            }
+        }
+    }
+}
+
+/// A key and value pair whose value is kept alive only while the key is reachable.
+///
+/// Neither object is kept alive by the ephemeron itself, and the value does not keep the key
+/// alive even if it refers to it. Both are cleared once the key is collected. Create one with
+/// [`GcContext::ephemeron`].
+pub struct GcEphemeron<'c, K: GcTarget<'c> + ?Sized + 'c, V: GcTarget<'c> + ?Sized + 'c> {
+    key: NonNull<GcBox<'c, K>>,
+    value: NonNull<GcBox<'c, V>>,
+    slot: Rc<GcEphemeronSlot<'c>>,
+}
+
+impl<'c, K: GcTarget<'c> + ?Sized + 'c, V: GcTarget<'c> + ?Sized + 'c> GcEphemeron<'c, K, V> {
+    fn is_live_node(node: Option<NonNullGcBox<'c>>) -> bool {
+        node.is_some_and(|node| {
+            matches!(
+                unsafe { node.as_ref() }.info.state.get(),
+                GcState::Active | GcState::Tracked | GcState::Pending
+            )
+        })
+    }
+
+    pub fn is_live(&self) -> bool {
+        Self::is_live_node(self.slot.key.get())
+    }
+
+    #[must_use = "dropping this root makes the object eligible for collection"]
+    pub fn key(&self) -> Option<GcRoot<'c, K>> {
+        if self.is_live() {
+            Some(unsafe { GcRoot::from_box(self.key) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value while the key is alive.
+    #[must_use = "dropping this root makes the object eligible for collection"]
+    pub fn value(&self) -> Option<GcRoot<'c, V>> {
+        if self.is_live() && Self::is_live_node(self.slot.value.get()) {
+            Some(unsafe { GcRoot::from_box(self.value) })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'c, K: GcTarget<'c> + ?Sized + 'c, V: GcTarget<'c> + ?Sized + 'c> Clone
+    for GcEphemeron<'c, K, V>
+{
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key,
+            value: self.value,
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<'c, K: GcTarget<'c> + ?Sized + 'c, V: GcTarget<'c> + ?Sized + 'c> Debug
+    for GcEphemeron<'c, K, V>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("GcEphemeron")
+            .field(&self.key)
+            .field(&self.value)
+            .finish()
+    }
+}
+
+/// Ephemerons are traced by the collector, not by their owner.
+impl<'c, K: GcTarget<'c> + ?Sized + 'c, V: GcTarget<'c> + ?Sized + 'c> GcTarget<'c>
+    for GcEphemeron<'c, K, V>
+{
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        let _ = token;
+    }
+}
+
 pub struct GcTraceToken<'c> {
     head: Option<NonNullGcBox<'c>>,
+    len: usize,
+    incomplete: bool,
+    depth: usize,
+    depth_limit: usize,
+    profile: Option<HashMap<&'static str, usize>>,
+    current: Option<NonNullGcBox<'c>>,
+    parents: Option<HashMap<usize, NonNullGcBox<'c>>>,
+    edges: Option<Vec<usize>>,
+    shared: HashSet<*const ()>,
 }
 
 impl<'c> GcTraceToken<'c> {
+    fn new(depth_limit: usize) -> Self {
+        Self {
+            head: None,
+            len: 0,
+            incomplete: false,
+            depth: 0,
+            depth_limit,
+            profile: None,
+            current: None,
+            parents: None,
+            edges: None,
+            shared: HashSet::new(),
+        }
+    }
+
+    fn for_context(context: &GcContextRaw<'c>) -> Self {
+        let mut r = Self::new(context.trace_depth_limit.get());
+        if context.trace_profiling.get() {
+            r.profile = Some(HashMap::new());
+        }
+        r
+    }
+
+    unsafe fn trace_node(&mut self, node: NonNullGcBox<'c>) {
+        let value = &*node.as_ref().value();
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(value.type_name()).or_default() += 1;
+        }
+        self.current = Some(node);
+        value.trace(self);
+    }
+
+    unsafe fn trace_all(&mut self) {
+        while let Some(node) = self.pop() {
+            self.trace_node(node);
+        }
+    }
+
     unsafe fn push(&mut self, node: NonNullGcBox<'c>) {
         let node = node.as_ref();
         node.info.next.set(self.head);
         self.head = NonNullGcBox::from_ptr(node);
+        self.len += 1;
     }
 
     unsafe fn pop(&mut self) -> Option<NonNullGcBox<'c>> {
         let r = self.head;
-        if r != None {
+        if r.is_some() {
             self.head = r.unwrap_unchecked().as_ref().info.next.get();
+            self.len -= 1;
             r
         } else {
             None
@@ -507,13 +1031,21 @@ impl<'c> GcTraceToken<'c> {
 
     unsafe fn accept_box<T: GcTarget<'c> + ?Sized + 'c>(&mut self, value: NonNull<GcBox<'c, T>>) {
         let value = value.as_ref();
+        if let Some(edges) = &mut self.edges {
+            edges.push(value as *const GcBox<'c, T> as *const () as usize);
+            return;
+        }
         match value.info.state.get() {
-            GcState::Untracked => {
+            GcState::Untracked | GcState::Pending => {
+                if let (Some(parents), Some(current)) = (&mut self.parents, self.current) {
+                    parents.insert(value as *const GcBox<'c, T> as *const () as usize, current);
+                }
                 value.info.state.set(GcState::Tracked);
                 value.info.next.set(self.head);
                 self.head = NonNullGcBox::from_ptr(value);
+                self.len += 1;
             }
-            GcState::Tracked | GcState::Active | GcState::Dropped => {}
+            GcState::Tracked | GcState::Active | GcState::Dropped | GcState::Uninit => {}
         }
     }
 
@@ -524,73 +1056,450 @@ impl<'c> GcTraceToken<'c> {
     pub fn accept_thin(&mut self, value: &GcObjectThin<'c>) {
         unsafe { self.accept_box(value.ptr.as_non_null()) };
     }
-}
 
-pub trait GcTarget<'c> {
-    fn trace(&self, token: &mut GcTraceToken<'c>);
-}
-
-struct GcNodeBackIter<'c> {
-    node: Option<NonNullGcBox<'c>>,
-}
+    /// Returns whether the object was already reached in this collection.
+    ///
+    /// Lets a trace impl skip enumerating children it knows are marked, `accept` alone already
+    /// ignores marked objects.
+    pub fn is_marked<T: GcTarget<'c> + ?Sized + 'c>(&self, value: &GcObject<'c, T>) -> bool {
+        unsafe { value.ptr.as_ref().info.state.get() == GcState::Tracked }
+    }
 
-impl<'c> GcNodeBackIter<'c> {
-    unsafe fn clone(&self) -> Self {
-        Self { node: self.node }
+    pub fn is_marked_thin(&self, value: &GcObjectThin<'c>) -> bool {
+        unsafe { value.ptr.as_non_null().as_ref().info.state.get() == GcState::Tracked }
     }
 
-    fn steal(gc: &GcContextRaw<'c>) -> Self {
-        let head = gc.head.deref();
-        let tail = gc.tail.deref();
-        let left = gc.head.info.next.get();
-        let right = gc.tail.info.prev.get();
-        if left == NonNullGcBox::from_ptr(tail) {
-            debug_assert!(right == NonNullGcBox::from_ptr(head));
-            return Self { node: None };
+    /// Traces a value owned by the one being traced, counting towards the trace depth limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the nesting exceeds the limit set by `GcContext::set_trace_depth_limit`.
+    pub fn trace_nested<T: GcTarget<'c> + ?Sized>(&mut self, value: &T) {
+        self.depth += 1;
+        if self.depth_limit != 0 && self.depth > self.depth_limit {
+            panic!("trace recursion too deep, likely a buggy trace impl");
         }
+        value.trace(self);
+        self.depth -= 1;
+    }
 
-        head.info.next.set(NonNullGcBox::from_ptr(tail));
-        tail.info.prev.set(NonNullGcBox::from_ptr(head));
-        unsafe {
-            left.unwrap_unchecked().as_ref().info.prev.set(None);
-            right.unwrap_unchecked().as_ref().info.next.set(None);
-            Self { node: right }
-        }
+    /// Returns `true` the first time `ptr` is visited during this trace.
+    ///
+    /// Shared pointers like `Rc` call this with the address of their pointee and only trace it
+    /// the first time, so a shared subtree is traced once however many paths reach it.
+    pub fn visit_shared(&mut self, ptr: *const ()) -> bool {
+        self.shared.insert(ptr)
     }
 
-    fn is_empty(&self) -> bool {
-        self.node == None
+    /// Returns the number of objects waiting to be traced.
+    pub fn worklist_len(&self) -> usize {
+        self.len
     }
-}
 
-impl<'c> Iterator for GcNodeBackIter<'c> {
-    type Item = NonNullGcBox<'c>;
+    /// Reports that some edges could not be traced, so this collection frees nothing.
+    pub fn set_incomplete(&mut self) {
+        self.incomplete = true;
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let current = self.node;
-        if let Some(current) = current {
-            self.node = unsafe { current.as_ref().info.prev.get() };
+    /// Traces the initialized prefix of an inline buffer.
+    ///
+    /// # Safety
+    ///
+    /// Every element of `init` must be initialized.
+    pub unsafe fn trace_maybe_uninit_slice<T: GcTarget<'c>>(&mut self, init: &[MaybeUninit<T>]) {
+        for i in init {
+            i.assume_init_ref().trace(self);
         }
-        current
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-enum GcContextState {
-    Normal,
-    Gc,
+/// A type whose gc handles can be traced.
+///
+/// Iterators and other adapters that borrow a container, like `Map<slice::Iter<T>, F>` or
+/// `btree_map::Range`, are not traceable: they are consumed as they are read, and a closure can
+/// capture handles that a trace can not see. Collect the items into a `Vec` before storing them.
+///
+/// ```compile_fail
+/// use regc::GcContext;
+///
+/// let context = GcContext::new();
+/// let values = vec![1, 2, 3];
+/// context.alloc(values.iter().map(|x| x * 2));
+/// ```
+///
+/// ```
+/// use regc::GcContext;
+///
+/// let context = GcContext::new();
+/// let values = vec![1, 2, 3];
+/// context.alloc(values.iter().map(|x| x * 2).collect::<Vec<_>>());
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can not be traced by the gc",
+    note = "implement `GcTarget`, derive `Trace`, or use `trace_none!` if it holds no gc handles",
+    note = "iterators can not be traced, collect them into a `Vec` first"
+)]
+pub trait GcTarget<'c> {
+    fn trace(&self, token: &mut GcTraceToken<'c>);
+
+    /// Returns `Some(self)` to make the type visible to downcasts and type queries.
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+
+    /// Called once before the object is dropped by
+    /// [`GcContext::collect_to_completion_with_finalizers`].
+    ///
+    /// A finalizer may resurrect unreachable objects by storing new handles to them
+    /// somewhere reachable, those objects are kept alive. Finalizers are not run by
+    /// [`GcContext::gc`] or when an object is freed by reference counting.
+    fn finalize(&self) {}
 }
 
+/// A traceable value that can be downcast, the replacement for `dyn Any` in gc objects.
+///
+/// `Any` needs `'static`, so downcasts are keyed on `Static`, the type with its context
+/// lifetime replaced by `'static`. `Box<dyn GcAny>` traces through the value, including the
+/// handles it holds. Implement it with [`gc_any!`].
+///
+/// # Safety
+///
+/// `Static` must be `Self` with `'c` replaced by `'static`, `Self` may have no other lifetime,
+/// and `static_type_id` must return `TypeId::of::<Self::Static>()`.
+pub unsafe trait GcAny<'c>: GcTarget<'c> {
+    type Static: ?Sized + 'static
+    where
+        Self: Sized;
+
+    fn static_type_id(&self) -> TypeId;
+}
+
+impl<'c> dyn GcAny<'c> + '_ {
+    pub fn is<T: GcAny<'c>>(&self) -> bool {
+        self.static_type_id() == TypeId::of::<T::Static>()
+    }
+
+    pub fn downcast_ref<T: GcAny<'c>>(&self) -> Option<&T> {
+        if self.is::<T>() {
+            Some(unsafe { &*(self as *const Self as *const T) })
+        } else {
+            None
+        }
+    }
+
+    pub fn downcast_mut<T: GcAny<'c>>(&mut self) -> Option<&mut T> {
+        if self.is::<T>() {
+            Some(unsafe { &mut *(self as *mut Self as *mut T) })
+        } else {
+            None
+        }
+    }
+}
+
+/// Implements [`GcAny`] for `'static` types, or for types whose only lifetime is `'c` given
+/// as `Type<'c> => Type<'static>`.
+///
+/// ```
+/// use regc::{GcObject, GcTarget, GcTraceToken};
+///
+/// struct Point(i32, i32);
+/// regc::trace_none!(Point);
+///
+/// struct Node<'c>(Option<GcObject<'c, Node<'c>>>);
+///
+/// impl<'c> GcTarget<'c> for Node<'c> {
+///     fn trace(&self, token: &mut GcTraceToken<'c>) {
+///         self.0.trace(token);
+///     }
+/// }
+///
+/// regc::gc_any!(Point, Node<'c> => Node<'static>);
+/// ```
+#[macro_export]
+macro_rules! gc_any {
+    (@static $type:ty) => { $type };
+    (@static $type:ty => $static:ty) => { $static };
+    ($($type:ty $(=> $static:ty)?),+ $(,)?) => {
+        $(
+            unsafe impl<'c> $crate::GcAny<'c> for $type {
+                type Static = $crate::gc_any!(@static $type $(=> $static)?);
+
+                fn static_type_id(&self) -> ::std::any::TypeId {
+                    ::std::any::TypeId::of::<Self::Static>()
+                }
+            }
+        )+
+    };
+}
+
+struct GcNodeIter<'c> {
+    node: Option<NonNullGcBox<'c>>,
+    tail: Option<NonNullGcBox<'c>>,
+}
+
+impl<'c> GcNodeIter<'c> {
+    fn new(gc: &GcContextRaw<'c>) -> Self {
+        Self::between(&gc.head, &gc.tail)
+    }
+
+    fn frozen(gc: &GcContextRaw<'c>) -> Self {
+        Self::between(&gc.frozen_head, &gc.frozen_tail)
+    }
+
+    fn between(head: &GcBox<'c, ()>, tail: &GcBox<'c, ()>) -> Self {
+        Self {
+            node: head.info.next.get(),
+            tail: NonNullGcBox::from_ptr(tail),
+        }
+    }
+}
+
+impl<'c> Iterator for GcNodeIter<'c> {
+    type Item = NonNullGcBox<'c>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.node;
+        if current == self.tail {
+            return None;
+        }
+        self.node = unsafe { current?.as_ref().info.next.get() };
+        current
+    }
+}
+
+struct GcNodeBackIter<'c> {
+    node: Option<NonNullGcBox<'c>>,
+}
+
+impl<'c> GcNodeBackIter<'c> {
+    unsafe fn clone(&self) -> Self {
+        Self { node: self.node }
+    }
+
+    fn steal(gc: &GcContextRaw<'c>) -> Self {
+        let head = gc.head.deref();
+        let tail = gc.tail.deref();
+        let left = gc.head.info.next.get();
+        let right = gc.tail.info.prev.get();
+        if left == NonNullGcBox::from_ptr(tail) {
+            debug_assert!(right == NonNullGcBox::from_ptr(head));
+            return Self { node: None };
+        }
+
+        head.info.next.set(NonNullGcBox::from_ptr(tail));
+        tail.info.prev.set(NonNullGcBox::from_ptr(head));
+        unsafe {
+            left.unwrap_unchecked().as_ref().info.prev.set(None);
+            right.unwrap_unchecked().as_ref().info.next.set(None);
+            Self { node: right }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.node.is_none()
+    }
+}
+
+impl<'c> Iterator for GcNodeBackIter<'c> {
+    type Item = NonNullGcBox<'c>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.node;
+        if let Some(current) = current {
+            self.node = unsafe { current.as_ref().info.prev.get() };
+        }
+        current
+    }
+}
+
+/// Relinks and holds every stolen object if a trace or a finalizer panics.
+struct GcMarkGuard<'s, 'c>(&'s GcContextRaw<'c>, Option<GcNodeBackIter<'c>>);
+
+impl<'s, 'c> Drop for GcMarkGuard<'s, 'c> {
+    fn drop(&mut self) {
+        if let Some(iter) = self.1.take() {
+            warn!("panic during mark, hold all target");
+            unsafe { self.0.sweep(iter, true) };
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum GcContextState {
+    Normal,
+    Gc,
+    Incremental,
+}
+
+/// The allocator failed to provide memory for an object.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct GcAllocError;
+
+impl Display for GcAllocError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl Error for GcAllocError {}
+
+/// Heap statistics passed to the predicate of `GcContext::set_gc_trigger`.
+///
+/// Live estimates are the objects held by the last collection plus everything allocated
+/// since, objects freed by reference counting are not subtracted.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct GcStats {
+    pub estimated_live_objects: usize,
+    pub estimated_live_bytes: usize,
+    pub allocs_since_gc: usize,
+    pub bytes_since_gc: usize,
+    pub since_last_gc: Duration,
+}
+
+/// Order in which a collection drops the values of unreachable objects.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum DropOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct GcCollectReport {
+    pub traced: usize,
+    pub held: usize,
+    pub dropped: usize,
+}
+
+type TypeFinalizer = Rc<dyn Fn(&dyn Any)>;
+
+type GcTrigger = Rc<dyn Fn(&GcStats) -> bool>;
+
+type PostGcHook<'c> = Box<dyn FnMut() -> bool + 'c>;
+
+/// A point where the injector of the `testing` feature can fail.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FaultPoint {
+    /// `try_alloc` fails and the other allocation methods panic, before anything is allocated.
+    BeforeAlloc,
+    /// A collection panics after marking the roots, as if a `trace` panicked.
+    DuringMark,
+    /// A collection panics before dropping the garbage, which is leaked.
+    DuringSweep,
+}
+
+#[cfg(feature = "testing")]
+type FaultInjector = Rc<dyn Fn(FaultPoint) -> bool>;
+
+/// A freed block sent to the thread of the `background-free` feature.
+#[cfg(feature = "background-free")]
+struct GcFreeBlock(NonNull<u8>, Layout);
+
+// The block is not referenced anymore, only the allocator sees it again.
+#[cfg(feature = "background-free")]
+unsafe impl Send for GcFreeBlock {}
+
+/// The thread that releases the blocks freed by `GcContext::collect_background`.
+#[cfg(feature = "background-free")]
+struct GcFreeThread {
+    sender: std::sync::mpsc::Sender<Vec<GcFreeBlock>>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+#[cfg(feature = "background-free")]
+impl GcFreeThread {
+    fn spawn() -> Option<Self> {
+        let (sender, receiver) = std::sync::mpsc::channel::<Vec<GcFreeBlock>>();
+        let thread = std::thread::Builder::new()
+            .name("regc-free".to_string())
+            .spawn(move || {
+                for blocks in receiver {
+                    for GcFreeBlock(block, layout) in blocks {
+                        unsafe { std::alloc::dealloc(block.as_ptr(), layout) };
+                    }
+                }
+            });
+        match thread {
+            Ok(thread) => Some(Self { sender, thread }),
+            Err(e) => {
+                warn!("spawn free thread failed: {}", e);
+                None
+            }
+        }
+    }
+
+    fn join(self) {
+        drop(self.sender);
+        if self.thread.join().is_err() {
+            warn!("free thread panicked");
+        }
+    }
+}
+
+/// Freed blocks kept per layout by the `pooling` feature.
+#[cfg(feature = "pooling")]
+const POOL_LIMIT: usize = 4096;
+
 struct GcContextRaw<'c> {
-    auto_gc: usize,
+    auto_gc: Cell<usize>,
     state: Cell<GcContextState>,
     alloc_count: Cell<usize>,
+    alloc_total: Cell<usize>,
+    min_heap_before_gc: Cell<usize>,
+    warmed_up: Cell<bool>,
+    auto_gc_bytes: Cell<usize>,
+    alloc_bytes: Cell<usize>,
+    allocs_since_gc: Cell<usize>,
+    held_bytes: Cell<usize>,
+    held_count: Cell<usize>,
+    weak_slots: RefCell<Vec<std::rc::Weak<GcWeakSlot<'c>>>>,
+    ephemerons: RefCell<Vec<std::rc::Weak<GcEphemeronSlot<'c>>>>,
+    #[cfg(feature = "pooling")]
+    free_blocks: RefCell<Vec<(Layout, Vec<NonNull<u8>>)>>,
+    #[cfg(feature = "background-free")]
+    background_blocks: RefCell<Option<Vec<GcFreeBlock>>>,
+    #[cfg(feature = "background-free")]
+    free_thread: RefCell<Option<GcFreeThread>>,
+    last_gc_end: Cell<Instant>,
+    gc_trigger: RefCell<Option<GcTrigger>>,
+    post_gc_hooks: RefCell<Vec<PostGcHook<'c>>>,
+    #[cfg(feature = "testing")]
+    fault_injector: RefCell<Option<FaultInjector>>,
+    last_gc_duration: Cell<Duration>,
+    total_gc_duration: Cell<Duration>,
+    gc_count: Cell<usize>,
+    record_dropped: Cell<bool>,
+    panic_on_leak: Cell<bool>,
+    drop_order: Cell<DropOrder>,
+    trace_depth_limit: Cell<usize>,
+    type_finalizers: RefCell<HashMap<TypeId, (&'static str, TypeFinalizer)>>,
+    suspended_gc: Cell<Option<SuspendedGc<'c>>>,
+    last_gc_dropped: RefCell<Vec<(usize, &'static str)>>,
+    trace_profiling: Cell<bool>,
+    stress: Cell<bool>,
+    paused: Cell<usize>,
+    lazy_sweep: Cell<bool>,
+    pending_values: Cell<Option<NonNullGcBox<'c>>>,
+    pending_boxes: Cell<Option<NonNullGcBox<'c>>>,
+    pending_count: Cell<usize>,
+    last_trace_profile: RefCell<Vec<(&'static str, usize)>>,
     head: Box<GcBox<'c, ()>>,
     tail: Box<GcBox<'c, ()>>,
+    frozen_head: Box<GcBox<'c, ()>>,
+    frozen_tail: Box<GcBox<'c, ()>>,
+    #[cfg(feature = "debug-checks")]
+    id: usize,
 }
 
 impl<'c> GcContextRaw<'c> {
     fn new() -> Self {
+        #[cfg(feature = "debug-checks")]
+        static NEXT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
+
         let head = Box::new(GcBox::new(()));
         head.info.root.set(1);
         let tail = Box::new(GcBox::new(()));
@@ -599,242 +1508,4464 @@ impl<'c> GcContextRaw<'c> {
         head.info.next.set(NonNullGcBox::from_ptr(tail.deref()));
         tail.info.prev.set(NonNullGcBox::from_ptr(head.deref()));
 
+        let frozen_head = Box::new(GcBox::new(()));
+        frozen_head.info.root.set(1);
+        let frozen_tail = Box::new(GcBox::new(()));
+        frozen_tail.info.root.set(1);
+
+        frozen_head
+            .info
+            .next
+            .set(NonNullGcBox::from_ptr(frozen_tail.deref()));
+        frozen_tail
+            .info
+            .prev
+            .set(NonNullGcBox::from_ptr(frozen_head.deref()));
+
         Self {
             state: Cell::new(GcContextState::Normal),
-            auto_gc: 0,
+            auto_gc: Cell::new(0),
             alloc_count: Cell::new(0),
+            alloc_total: Cell::new(0),
+            min_heap_before_gc: Cell::new(0),
+            warmed_up: Cell::new(false),
+            auto_gc_bytes: Cell::new(0),
+            alloc_bytes: Cell::new(0),
+            allocs_since_gc: Cell::new(0),
+            held_bytes: Cell::new(0),
+            held_count: Cell::new(0),
+            weak_slots: RefCell::new(Vec::new()),
+            ephemerons: RefCell::new(Vec::new()),
+            #[cfg(feature = "pooling")]
+            free_blocks: RefCell::new(Vec::new()),
+            #[cfg(feature = "background-free")]
+            background_blocks: RefCell::new(None),
+            #[cfg(feature = "background-free")]
+            free_thread: RefCell::new(None),
+            last_gc_end: Cell::new(Instant::now()),
+            gc_trigger: RefCell::new(None),
+            post_gc_hooks: RefCell::new(Vec::new()),
+            #[cfg(feature = "testing")]
+            fault_injector: RefCell::new(None),
+            last_gc_duration: Cell::new(Duration::ZERO),
+            total_gc_duration: Cell::new(Duration::ZERO),
+            gc_count: Cell::new(0),
+            record_dropped: Cell::new(false),
+            panic_on_leak: Cell::new(false),
+            drop_order: Cell::new(DropOrder::NewestFirst),
+            trace_depth_limit: Cell::new(0),
+            type_finalizers: RefCell::new(HashMap::new()),
+            suspended_gc: Cell::new(None),
+            last_gc_dropped: RefCell::new(Vec::new()),
+            trace_profiling: Cell::new(false),
+            stress: Cell::new(false),
+            paused: Cell::new(0),
+            lazy_sweep: Cell::new(false),
+            pending_values: Cell::new(None),
+            pending_boxes: Cell::new(None),
+            pending_count: Cell::new(0),
+            last_trace_profile: RefCell::new(Vec::new()),
             head,
             tail,
+            frozen_head,
+            frozen_tail,
+            #[cfg(feature = "debug-checks")]
+            id: NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
         }
     }
 
-    fn set_auto_gc(&mut self, auto_gc: usize) {
-        self.auto_gc = auto_gc;
-        self.alloc_count.set(0);
-    }
-
-    fn alloc<T: GcTarget<'c> + 'c>(&'c self, value: T) -> GcRoot<'c, T> {
-        if self.auto_gc != 0 {
-            let alloc_count = self.alloc_count.get() + 1;
-            if alloc_count == self.auto_gc {
-                self.alloc_count.set(0);
-                info!("auto gc");
-                self.gc();
-            } else {
-                self.alloc_count.set(alloc_count);
-            }
+    /// Moves every node between `from_head` and `from_tail` to the end of the list ending
+    /// at `to_tail`.
+    fn move_list(from_head: &GcBox<'c, ()>, from_tail: &GcBox<'c, ()>, to_tail: &GcBox<'c, ()>) {
+        let first = from_head.info.next.get();
+        if first == NonNullGcBox::from_ptr(from_tail) {
+            return;
         }
+        let last = from_tail.info.prev.get();
+        from_head.info.next.set(NonNullGcBox::from_ptr(from_tail));
+        from_tail.info.prev.set(NonNullGcBox::from_ptr(from_head));
 
-        let value = GcBox::alloc(value);
-        let value_ref = unsafe { value.as_ref() };
-        let value_ptr = NonNullGcBox::from_non_null(value);
-
-        let tail = self.tail.deref();
-        let prev = tail.info.prev.get();
-
-        value_ref.info.prev.set(prev);
-        value_ref.info.next.set(NonNullGcBox::from_ptr(tail));
+        let prev = to_tail.info.prev.get();
         unsafe {
-            prev.unwrap_unchecked()
+            prev.unwrap_unchecked().as_ref().info.next.set(first);
+            first.unwrap_unchecked().as_ref().info.prev.set(prev);
+            last.unwrap_unchecked()
                 .as_ref()
                 .info
                 .next
-                .set(Some(value_ptr))
-        };
-        tail.info.prev.set(Some(value_ptr));
-
-        unsafe { GcRoot::from_box(value) }
+                .set(NonNullGcBox::from_ptr(to_tail));
+        }
+        to_tail.info.prev.set(last);
     }
 
-    fn gc(&self) {
-        info!("call gc");
-        match self.state.get() {
-            GcContextState::Normal => {
-                struct Guard<'s, 'c>(&'s GcContextRaw<'c>, std::time::Instant);
-
-                impl<'s, 'c> Drop for Guard<'s, 'c> {
-                    fn drop(&mut self) {
-                        self.0.state.set(GcContextState::Normal);
-                        info!("end gc {:?}", self.1.elapsed());
-                    }
-                }
+    fn freeze_current(&self) {
+        info!("freeze current target");
+        Self::move_list(&self.head, &self.tail, &self.frozen_tail);
+    }
 
-                info!("begin gc");
-                self.state.set(GcContextState::Gc);
-                let _guard = Guard(self, std::time::Instant::now());
+    fn unfreeze(&self) {
+        info!("unfreeze target");
+        Self::move_list(&self.frozen_head, &self.frozen_tail, &self.tail);
+    }
 
-                let iter = GcNodeBackIter::steal(self);
-                if iter.is_empty() {
-                    return;
+    /// Marks the values of ephemerons with marked keys, until no more values are marked.
+    unsafe fn trace_ephemerons(&self, token: &mut GcTraceToken<'c>) {
+        let ephemerons = self.ephemerons.borrow();
+        loop {
+            let mut count = 0;
+            for slot in ephemerons.iter().filter_map(std::rc::Weak::upgrade) {
+                let (Some(key), Some(value)) = (slot.key.get(), slot.value.get()) else {
+                    continue;
+                };
+                let key_marked = matches!(
+                    key.as_ref().info.state.get(),
+                    GcState::Active | GcState::Tracked
+                );
+                let value_marked = matches!(
+                    value.as_ref().info.state.get(),
+                    GcState::Active | GcState::Tracked
+                );
+                if key_marked && !value_marked {
+                    token.current = Some(key);
+                    token.accept_box(value.as_non_null());
+                    count += 1;
                 }
-                unsafe {
-                    let mut token = GcTraceToken { head: None };
-
-                    let mut count = 0;
-                    for node in iter.clone() {
-                        count += 1;
-                        let n = node.as_ref();
-                        debug_assert!(n.info.state.get() == GcState::Active);
-                        if n.info.root.get() != 0 {
-                            n.info.state.set(GcState::Tracked);
-                            token.push(node);
-                        } else {
-                            n.info.state.set(GcState::Untracked);
-                        }
-                    }
-
-                    info!("trace {} target", count);
-
-                    while let Some(node) = token.pop() {
-                        let node = node.as_ref();
-                        let value = &*node.value();
-                        value.trace(&mut token);
-                    }
+            }
+            if count == 0 {
+                break;
+            }
+            trace!("ephemeron mark {} target", count);
+            token.trace_all();
+        }
+    }
 
-                    let mut hold_count = 0;
-                    let mut drop_count = 0;
-
-                    let mut that = self.head.info.next.get();
-                    for node in iter {
-                        let n = node.as_ref();
-                        let node = NonNullGcBox::from_ptr(n);
-                        match n.info.state.get() {
-                            GcState::Active | GcState::Dropped => unreachable!(),
-                            GcState::Tracked => {
-                                hold_count += 1;
-                                n.info.next.set(that);
-                                that.unwrap_unchecked().as_ref().info.prev.set(node);
-                                n.info.state.set(GcState::Active);
-                                that = node;
-                            }
-                            GcState::Untracked => {
-                                drop_count += 1;
-                                GcBox::drop_value(
-                                    &mut *node.unwrap_unchecked().as_ptr().cast_mut(),
-                                );
-                                GcBox::free(node.unwrap_unchecked().as_non_null());
-                            }
-                        }
-                    }
+    fn store_trace_profile(&self, token: &mut GcTraceToken<'c>) {
+        if let Some(profile) = token.profile.take() {
+            let mut profile = profile.into_iter().collect::<Vec<_>>();
+            profile.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            *self.last_trace_profile.borrow_mut() = profile;
+        }
+    }
 
-                    info!("hold {} target", hold_count);
-                    info!("drop {} target", drop_count);
+    /// Marks from the roots, recording the object each object was first reached from, and
+    /// returns the chain of addresses from a root to `target`.
+    fn retention_path(&self, target: NonNullGcBox<'c>) -> Option<Vec<usize>> {
+        assert_eq!(
+            self.state.get(),
+            GcContextState::Normal,
+            "retention path during collection"
+        );
+        // Restores the state and the held stats of the last collection, even if a trace panics.
+        struct Guard<'s, 'c>(&'s GcContextRaw<'c>, usize, usize);
 
-                    let head = self.head.deref();
-                    that.unwrap_unchecked()
-                        .as_ref()
-                        .info
-                        .prev
-                        .set(NonNullGcBox::from_ptr(head));
-                    head.info.next.set(that);
-                }
+        impl<'s, 'c> Drop for Guard<'s, 'c> {
+            fn drop(&mut self) {
+                self.0.held_count.set(self.1);
+                self.0.held_bytes.set(self.2);
+                self.0.state.set(GcContextState::Normal);
             }
-            GcContextState::Gc => {}
         }
-    }
-}
 
-impl<'c> Drop for GcContextRaw<'c> {
-    fn drop(&mut self) {
-        info!("drop gc");
-        self.gc();
+        self.state.set(GcContextState::Gc);
+        let _guard = Guard(self, self.held_count.get(), self.held_bytes.get());
         let iter = GcNodeBackIter::steal(self);
-        let mut leak_count = 0;
-        for node in iter {
-            trace!("leak {:?}", node.as_ptr());
-            leak_count += 1;
-            let n = unsafe { node.as_ref() };
-            debug_assert!(n.info.state.get() == GcState::Active);
-            n.info.prev.set(None);
-            n.info.next.set(None);
-            n.info.root.set(n.info.root.get() + 1);
+        let mut mark = GcMarkGuard(self, Some(unsafe { iter.clone() }));
+        let mut token = GcTraceToken::new(self.trace_depth_limit.get());
+        token.parents = Some(HashMap::new());
+        let (reached, parents) = unsafe {
+            Self::mark_roots(&iter, &mut token, GcState::Untracked);
+            self.trace_frozen(&mut token);
+            token.trace_all();
+            self.trace_ephemerons(&mut token);
+            let reached = matches!(
+                target.as_ref().info.state.get(),
+                GcState::Tracked | GcState::Active
+            );
+            let parents = token.parents.take().unwrap_or_default();
+            mark.1 = None;
+            self.sweep(iter, true);
+            (reached, parents)
+        };
+        if !reached {
+            return None;
         }
-        if leak_count != 0 {
-            warn!("leak {} target", leak_count);
+        let mut path = vec![target.ptr.as_ptr() as usize];
+        while let Some(parent) = parents.get(path.last().unwrap()) {
+            path.push(parent.ptr.as_ptr() as usize);
         }
+        path.reverse();
+        Some(path)
     }
-}
 
-impl<'c> Debug for GcContextRaw<'c> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("GcContextRaw")
-            .field("auto_gc", &self.auto_gc)
-            .field("alloc_count", &self.alloc_count.get())
-            .finish()
+    /// Frozen objects are never swept but may still point to young objects, so they are
+    /// traced like roots.
+    unsafe fn trace_frozen(&self, token: &mut GcTraceToken<'c>) {
+        for node in GcNodeIter::frozen(self) {
+            token.trace_node(node);
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct GcContext<'c> {
-    inner: GcContextRaw<'static>,
-    marker: PhantomData<*mut &'c ()>,
-}
+    fn set_auto_gc(&self, auto_gc: usize) {
+        self.auto_gc.set(auto_gc);
+        self.alloc_count.set(0);
+    }
 
-impl<'c> GcContext<'c> {
-    fn inner(&self) -> &GcContextRaw<'c> {
-        unsafe { &*((&self.inner as *const GcContextRaw<'static>).cast()) }
+    fn set_auto_gc_bytes(&self, auto_gc_bytes: usize) {
+        self.auto_gc_bytes.set(auto_gc_bytes);
     }
 
-    fn inner_mut(&mut self) -> &mut GcContextRaw<'c> {
-        unsafe { &mut *((&mut self.inner as *mut GcContextRaw<'static>).cast()) }
+    /// Asks the injector of the `testing` feature whether to fail at `point`.
+    #[inline]
+    fn inject_fault(&self, point: FaultPoint) -> bool {
+        #[cfg(feature = "testing")]
+        {
+            let injector = self.fault_injector.borrow().clone();
+            if let Some(injector) = injector {
+                return injector(point);
+            }
+        }
+        let _ = point;
+        false
     }
 
-    pub fn new() -> Self {
-        Self {
-            inner: GcContextRaw::new(),
-            marker: PhantomData,
+    fn check_alloc_fault(&self) {
+        if self.inject_fault(FaultPoint::BeforeAlloc) {
+            panic!("injected allocation failure");
         }
     }
 
-    pub fn set_auto_gc(&mut self, auto_gc: usize) {
-        self.inner_mut().set_auto_gc(auto_gc);
+    #[track_caller]
+    fn alloc<T: GcTarget<'c> + 'c>(&'c self, value: T) -> GcRoot<'c, T> {
+        self.check_alloc_fault();
+        self.check_auto_gc(size_of::<GcBox<'c, T>>());
+        let r = self.alloc_unchecked(value);
+        self.check_gc_trigger();
+        r
     }
 
-    pub fn alloc<T: GcTarget<'c> + 'c>(&'c self, value: T) -> GcRoot<'c, T> {
-        self.inner().alloc(value)
+    #[track_caller]
+    fn alloc_batch<T: GcTarget<'c> + 'c>(
+        &'c self,
+        items: impl IntoIterator<Item = T>,
+    ) -> Vec<GcRoot<'c, T>> {
+        let mut items = items.into_iter().peekable();
+        if items.peek().is_none() {
+            return Vec::new();
+        }
+        self.check_alloc_fault();
+        self.check_auto_gc(size_of::<GcBox<'c, T>>());
+        let mut roots = Vec::with_capacity(items.size_hint().0);
+        roots.push(self.alloc_unchecked(items.next().unwrap()));
+        for item in items {
+            self.alloc_total.set(self.alloc_total.get() + 1);
+            if self.auto_gc.get() != 0 {
+                self.alloc_count.set(self.alloc_count.get() + 1);
+            }
+            roots.push(self.alloc_unchecked(item));
+        }
+        self.check_gc_trigger();
+        roots
     }
 
-    pub fn gc(&self) {
-        self.inner().gc()
+    fn check_auto_gc(&self, size: usize) {
+        let auto_gc = self.auto_gc.get();
+        let auto_gc_bytes = self.auto_gc_bytes.get();
+        let alloc_count = self.alloc_count.get() + 1;
+        self.alloc_total.set(self.alloc_total.get() + 1);
+        if !self.warmed_up.get() {
+            let live = self.held_count.get() + self.allocs_since_gc.get() + 1;
+            self.warmed_up.set(live > self.min_heap_before_gc.get());
+        }
+        if self.paused.get() != 0 {
+            trace!("skip auto gc while paused");
+        } else if self.stress.get() {
+            trace!("stress gc");
+            self.gc();
+        } else if !self.warmed_up.get() {
+            trace!("skip auto gc during warm up");
+        } else if (auto_gc != 0 && alloc_count >= auto_gc)
+            || (auto_gc_bytes != 0 && self.alloc_bytes.get() + size >= auto_gc_bytes)
+        {
+            self.alloc_count.set(0);
+            info!("auto gc");
+            self.gc();
+        } else if auto_gc != 0 {
+            self.alloc_count.set(alloc_count);
+        }
     }
-}
 
-#[macro_export]
-macro_rules! trace_none {
-    ($type:ty) => {
-        impl<'c> $crate::GcTarget<'c> for $type {
-            fn trace(&self, token: &mut $crate::GcTraceToken<'c>) {
-                let _ = token;
+    fn stats(&self) -> GcStats {
+        let allocs_since_gc = self.allocs_since_gc.get();
+        GcStats {
+            estimated_live_objects: self.held_count.get() + allocs_since_gc,
+            estimated_live_bytes: self.held_bytes.get() + self.alloc_bytes.get(),
+            allocs_since_gc,
+            bytes_since_gc: self.alloc_bytes.get(),
+            since_last_gc: self.last_gc_end.get().elapsed(),
+        }
+    }
+
+    fn check_gc_trigger(&self) {
+        if self.state.get() != GcContextState::Normal || self.paused.get() != 0 {
+            return;
+        }
+        let trigger = self.gc_trigger.borrow().clone();
+        if let Some(trigger) = trigger {
+            if trigger(&self.stats()) {
+                info!("triggered gc");
+                self.gc();
             }
         }
-    };
-}
+    }
 
-#[test]
+    #[track_caller]
+    fn alloc_unchecked<T: GcTarget<'c> + 'c>(&'c self, value: T) -> GcRoot<'c, T> {
+        match self.alloc_pooled(value) {
+            Ok(r) => self.link(r),
+            Err(value) => self.link(GcBox::alloc(value)),
+        }
+    }
+
+    /// Reuses a freed box of the same layout, with the `pooling` feature.
+    fn alloc_pooled<T: GcTarget<'c> + 'c>(&self, value: T) -> Result<NonNull<GcBox<'c, T>>, T> {
+        #[cfg(feature = "pooling")]
+        {
+            let layout = Layout::new::<GcBox<'c, T>>();
+            let block = self
+                .free_blocks
+                .borrow_mut()
+                .iter_mut()
+                .find(|(l, _)| *l == layout)
+                .and_then(|(_, blocks)| blocks.pop());
+            if let Some(block) = block {
+                let r = block.cast::<GcBox<'c, T>>();
+                unsafe { r.as_ptr().write(GcBox::new(value)) };
+                trace!(
+                    "alloc {} {:?} pooled",
+                    type_name::<T>(),
+                    r.as_ptr() as *mut ()
+                );
+                return Ok(r);
+            }
+        }
+        Err(value)
+    }
+
+    /// Frees a box whose value was dropped, keeping the block for reuse with the `pooling`
+    /// feature, or handing it to the free thread during `collect_background`.
+    unsafe fn free_box(&self, node: NonNullGcBox<'c>) {
+        #[cfg(feature = "pooling")]
+        {
+            let layout = Layout::for_value(node.as_ref());
+            let mut free_blocks = self.free_blocks.borrow_mut();
+            let blocks = match free_blocks.iter().position(|(l, _)| *l == layout) {
+                Some(i) => &mut free_blocks[i].1,
+                None => {
+                    free_blocks.push((layout, Vec::new()));
+                    &mut free_blocks.last_mut().unwrap().1
+                }
+            };
+            if blocks.len() < POOL_LIMIT {
+                trace!("pool {:?}", node.as_ptr());
+                blocks.push(node.ptr.cast());
+                return;
+            }
+        }
+        #[cfg(feature = "background-free")]
+        if let Some(blocks) = self.background_blocks.borrow_mut().as_mut() {
+            // The box has no drop glue besides its value, which is already dropped.
+            blocks.push(GcFreeBlock(
+                node.ptr.cast(),
+                Layout::for_value(node.as_ref()),
+            ));
+            return;
+        }
+        GcBox::free(node.as_non_null());
+    }
+
+    /// Runs a collection and sends the freed blocks to the free thread, spawning it first.
+    #[cfg(feature = "background-free")]
+    fn collect_background(&self) -> Option<GcCollectReport> {
+        struct Guard<'s, 'c>(&'s GcContextRaw<'c>);
+
+        impl<'s, 'c> Drop for Guard<'s, 'c> {
+            fn drop(&mut self) {
+                let blocks = self.0.background_blocks.take().unwrap_or_default();
+                if blocks.is_empty() {
+                    return;
+                }
+                let mut free_thread = self.0.free_thread.borrow_mut();
+                if free_thread.is_none() {
+                    *free_thread = GcFreeThread::spawn();
+                }
+                let blocks = match &*free_thread {
+                    Some(free_thread) => match free_thread.sender.send(blocks) {
+                        Ok(()) => return,
+                        Err(e) => e.0,
+                    },
+                    None => blocks,
+                };
+                for GcFreeBlock(block, layout) in blocks {
+                    unsafe { std::alloc::dealloc(block.as_ptr(), layout) };
+                }
+            }
+        }
+
+        if self.state.get() != GcContextState::Normal {
+            return None;
+        }
+        self.background_blocks.replace(Some(Vec::new()));
+        let _guard = Guard(self);
+        self.collect(false, None)
+    }
+
+    #[cfg(feature = "pooling")]
+    fn compact_free_list(&self) {
+        let mut count = 0;
+        for (layout, blocks) in self.free_blocks.take() {
+            count += blocks.len();
+            for block in blocks {
+                unsafe { std::alloc::dealloc(block.as_ptr(), layout) };
+            }
+        }
+        info!("release {} pooled block", count);
+    }
+
+    #[track_caller]
+    fn try_alloc<T: GcTarget<'c> + 'c>(&'c self, value: T) -> Result<GcRoot<'c, T>, T> {
+        if self.inject_fault(FaultPoint::BeforeAlloc) {
+            return Err(value);
+        }
+        self.check_auto_gc(size_of::<GcBox<'c, T>>());
+        let r = match self.alloc_pooled(value) {
+            Ok(r) => self.link(r),
+            Err(value) => self.link(GcBox::try_alloc(value)?),
+        };
+        self.check_gc_trigger();
+        Ok(r)
+    }
+
+    #[track_caller]
+    fn alloc_cyclic_typed<T: GcTarget<'c> + 'c>(
+        &'c self,
+        f: impl FnOnce(&GcObject<'c, T>) -> T,
+    ) -> GcRoot<'c, T> {
+        self.check_alloc_fault();
+        self.check_auto_gc(size_of::<GcBox<'c, T>>());
+        // The layout matches `Box<GcBox<T>>`, so the box is freed by `free` as usual.
+        let layout = Layout::new::<GcBox<'c, T>>();
+        let Some(ptr) = NonNull::new(unsafe { alloc(layout) }.cast::<GcBox<'c, T>>()) else {
+            handle_alloc_error(layout)
+        };
+        let raw = ptr.as_ptr();
+        unsafe {
+            addr_of_mut!((*raw).metadata).write(GcBoxDynPtr::from_ptr(raw).metadata);
+            addr_of_mut!((*raw).info).write(GcInfo::new(GcState::Uninit));
+        }
+        trace!("alloc cyclic {} {:?}", type_name::<T>(), raw as *mut ());
+        let handle = unsafe { GcObject::from_box(ptr) };
+        let value = f(&handle);
+        unsafe {
+            addr_of_mut!((*raw).value).write(ManuallyDrop::new(value));
+            ptr.as_ref().info.state.set(GcState::Active);
+        }
+        let r = self.link(ptr);
+        drop(handle);
+        self.check_gc_trigger();
+        r
+    }
+
+    /// Asserts that the object was allocated by this context, with the `debug-checks` feature.
+    fn debug_check_owner(&self, info: &GcInfo<'c>) {
+        #[cfg(feature = "debug-checks")]
+        debug_assert_eq!(
+            info.context_id.get(),
+            self.id,
+            "object used with a context that did not allocate it"
+        );
+        let _ = info;
+    }
+
+    #[track_caller]
+    fn link<T: GcTarget<'c> + 'c>(&'c self, value: NonNull<GcBox<'c, T>>) -> GcRoot<'c, T> {
+        let size = size_of::<GcBox<'c, T>>();
+        self.alloc_bytes.set(self.alloc_bytes.get() + size);
+        self.allocs_since_gc.set(self.allocs_since_gc.get() + 1);
+
+        let value_ref = unsafe { value.as_ref() };
+        let value_ptr = NonNullGcBox::from_non_null(value);
+        #[cfg(feature = "debug-checks")]
+        value_ref.info.context_id.set(self.id);
+        #[cfg(feature = "alloc-tracking")]
+        value_ref.info.location.set(Some(Location::caller()));
+        #[cfg(feature = "pooling")]
+        value_ref.info.pool.set(Some(NonNull::from(self)));
+
+        let tail = self.tail.deref();
+        let prev = tail.info.prev.get();
+
+        value_ref.info.prev.set(prev);
+        value_ref.info.next.set(NonNullGcBox::from_ptr(tail));
+        unsafe {
+            prev.unwrap_unchecked()
+                .as_ref()
+                .info
+                .next
+                .set(Some(value_ptr))
+        };
+        tail.info.prev.set(Some(value_ptr));
+
+        unsafe { GcRoot::from_box(value) }
+    }
+
+    fn gc(&self) {
+        self.collect(false, None);
+    }
+
+    fn begin_gc(&self) {
+        // Objects set aside by `mark_only` may point to objects this collection frees.
+        unsafe { self.sweep_pending(usize::MAX) };
+        self.last_gc_dropped.borrow_mut().clear();
+        self.alloc_bytes.set(0);
+        self.allocs_since_gc.set(0);
+        self.held_bytes.set(0);
+        self.held_count.set(0);
+    }
+
+    fn end_gc(&self, elapsed: Duration) {
+        self.state.set(GcContextState::Normal);
+        self.last_gc_end.set(Instant::now());
+        self.last_gc_duration.set(elapsed);
+        self.total_gc_duration
+            .set(self.total_gc_duration.get() + elapsed);
+        self.gc_count.set(self.gc_count.get() + 1);
+        info!("end gc {:?}", elapsed);
+        if !std::thread::panicking() {
+            self.run_post_gc_hooks();
+        }
+    }
+
+    fn run_post_gc_hooks(&self) {
+        // hooks registered or collections started by a hook wait for the next collection
+        let mut hooks = self.post_gc_hooks.take();
+        if hooks.is_empty() {
+            return;
+        }
+        hooks.retain_mut(|hook| hook());
+        let mut added = self.post_gc_hooks.borrow_mut();
+        hooks.append(&mut added);
+        *added = hooks;
+    }
+
+    fn collect(
+        &self,
+        finalize: bool,
+        stack: Option<(*const usize, usize)>,
+    ) -> Option<GcCollectReport> {
+        info!("call gc");
+        match self.state.get() {
+            GcContextState::Normal => {
+                struct Guard<'s, 'c>(&'s GcContextRaw<'c>, Instant);
+
+                impl<'s, 'c> Drop for Guard<'s, 'c> {
+                    fn drop(&mut self) {
+                        self.0.end_gc(self.1.elapsed());
+                    }
+                }
+
+                info!("begin gc");
+                self.state.set(GcContextState::Gc);
+                let _guard = Guard(self, Instant::now());
+                self.begin_gc();
+
+                let mut report = GcCollectReport::default();
+                loop {
+                    let iter = GcNodeBackIter::steal(self);
+                    if iter.is_empty() {
+                        return Some(report);
+                    }
+                    unsafe {
+                        let mut mark = GcMarkGuard(self, Some(iter.clone()));
+                        let mut token = GcTraceToken::for_context(self);
+                        report.traced = Self::mark_roots(&iter, &mut token, GcState::Untracked);
+                        if self.inject_fault(FaultPoint::DuringMark) {
+                            panic!("injected panic during mark");
+                        }
+                        if let Some(stack) = stack {
+                            Self::mark_conservative(&iter, &mut token, stack);
+                        }
+                        self.trace_frozen(&mut token);
+                        token.trace_all();
+                        self.trace_ephemerons(&mut token);
+                        self.store_trace_profile(&mut token);
+
+                        let incomplete = token.incomplete;
+                        if incomplete {
+                            warn!("trace incomplete, hold all target");
+                        } else if finalize && self.finalize(&iter) {
+                            info!("finalized, trace again");
+                            mark.1 = None;
+                            self.sweep(iter, true);
+                            continue;
+                        }
+                        mark.1 = None;
+                        let (held, dropped) = self.sweep(iter, incomplete);
+                        report.held = held;
+                        report.dropped += dropped;
+                        return Some(report);
+                    }
+                }
+            }
+            GcContextState::Gc | GcContextState::Incremental => None,
+        }
+    }
+
+    unsafe fn finalize(&self, iter: &GcNodeBackIter<'c>) -> bool {
+        let mut count = 0;
+        for node in iter.clone() {
+            let n = node.as_ref();
+            if n.info.state.get() == GcState::Untracked && !n.info.finalized.get() {
+                n.info.finalized.set(true);
+                let value = &*n.value();
+                value.finalize();
+                if let Some(any) = value.as_any() {
+                    let f = self.type_finalizers.borrow().get(&any.type_id()).cloned();
+                    if let Some((_, f)) = f {
+                        f(any);
+                    }
+                } else {
+                    self.warn_unrecognized_finalized(value.type_name());
+                }
+                count += 1;
+            }
+        }
+        info!("finalize {} target", count);
+        count != 0
+    }
+
+    fn warn_unrecognized_finalized(&self, name: &str) {
+        let finalizers = self.type_finalizers.borrow();
+        if finalizers.values().any(|(type_name, _)| *type_name == name) {
+            warn!(
+                "{} does not override as_any, its type finalizer never runs",
+                name
+            );
+        }
+    }
+
+    unsafe fn mark_roots(
+        iter: &GcNodeBackIter<'c>,
+        token: &mut GcTraceToken<'c>,
+        unrooted: GcState,
+    ) -> usize {
+        let mut count = 0;
+        for node in iter.clone() {
+            count += 1;
+            let n = node.as_ref();
+            debug_assert!(n.info.state.get() == GcState::Active);
+            if n.info.root.get() != 0 {
+                n.info.state.set(GcState::Tracked);
+                token.push(node);
+            } else {
+                n.info.state.set(unrooted);
+            }
+        }
+        info!("trace {} target", count);
+        count
+    }
+
+    /// Treats every box whose address appears as a word in `stack` as a root.
+    unsafe fn mark_conservative(
+        iter: &GcNodeBackIter<'c>,
+        token: &mut GcTraceToken<'c>,
+        (start, len): (*const usize, usize),
+    ) {
+        let boxes = iter
+            .clone()
+            .map(|node| (node.ptr.as_ptr() as usize, node))
+            .collect::<HashMap<_, _>>();
+        let mut count = 0;
+        for i in 0..len {
+            let word = read_volatile(start.add(i));
+            if let Some(node) = boxes.get(&word) {
+                count += 1;
+                token.accept_box(node.as_non_null());
+            }
+        }
+        info!("conservative {} target", count);
+    }
+
+    /// Clears the weak handles and ephemerons of unmarked objects, and forgets dropped ones.
+    unsafe fn clear_weak_slots(&self) {
+        let unmarked = |cell: &Cell<Option<NonNullGcBox<'c>>>| {
+            if let Some(node) = cell.get() {
+                if node.as_ref().info.state.get() == GcState::Untracked {
+                    cell.set(None);
+                }
+            }
+        };
+        self.ephemerons.borrow_mut().retain(|slot| {
+            let Some(slot) = slot.upgrade() else {
+                return false;
+            };
+            unmarked(&slot.key);
+            unmarked(&slot.value);
+            slot.key.get().is_some() || slot.value.get().is_some()
+        });
+        self.weak_slots.borrow_mut().retain(|slot| {
+            let Some(slot) = slot.upgrade() else {
+                return false;
+            };
+            match slot.0.get() {
+                Some(node) if node.as_ref().info.state.get() == GcState::Untracked => {
+                    slot.0.set(None);
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            }
+        });
+    }
+
+    /// Drops up to `max` values set aside by `mark_only`, and frees every box once all of them
+    /// are dropped.
+    unsafe fn sweep_pending(&self, max: usize) -> usize {
+        let mut count = 0;
+        while count < max {
+            let Some(node) = self.pending_values.get() else {
+                break;
+            };
+            self.pending_values.set(node.as_ref().info.next.get());
+            node.as_ref().info.next.set(self.pending_boxes.get());
+            self.pending_boxes.set(Some(node));
+            GcBox::drop_value(&mut *node.as_ptr().cast_mut());
+            count += 1;
+        }
+        self.pending_count.set(self.pending_count.get() - count);
+        if self.pending_values.get().is_none() {
+            let mut garbage = self.pending_boxes.take();
+            while let Some(node) = garbage {
+                garbage = node.as_ref().info.next.get();
+                self.free_box(node);
+            }
+        }
+        if count != 0 {
+            info!("sweep {} pending target", count);
+        }
+        count
+    }
+
+    unsafe fn sweep(&self, iter: GcNodeBackIter<'c>, hold_all: bool) -> (usize, usize) {
+        let mut hold_count = 0;
+        let mut hold_bytes = 0;
+        let mut drop_count = 0;
+
+        let mut that = self.head.info.next.get();
+        let mut garbage_head = None;
+        let mut garbage_tail: Option<NonNullGcBox<'c>> = None;
+        for node in iter {
+            let n = node.as_ref();
+            let node = NonNullGcBox::from_ptr(n);
+            match n.info.state.get() {
+                GcState::Active | GcState::Dropped | GcState::Uninit => unreachable!(),
+                GcState::Untracked | GcState::Pending if !hold_all => {
+                    drop_count += 1;
+                    n.info.state.set(GcState::Untracked);
+                    match self.drop_order.get() {
+                        DropOrder::NewestFirst => {
+                            n.info.next.set(None);
+                            match garbage_tail {
+                                Some(tail) => tail.as_ref().info.next.set(node),
+                                None => garbage_head = node,
+                            }
+                            garbage_tail = node;
+                        }
+                        DropOrder::OldestFirst => {
+                            n.info.next.set(garbage_head);
+                            garbage_head = node;
+                        }
+                    }
+                }
+                GcState::Tracked | GcState::Untracked | GcState::Pending => {
+                    hold_count += 1;
+                    hold_bytes += size_of_val(n);
+                    n.info.next.set(that);
+                    that.unwrap_unchecked().as_ref().info.prev.set(node);
+                    n.info.state.set(GcState::Active);
+                    that = node;
+                }
+            }
+        }
+
+        self.clear_weak_slots();
+        info!("hold {} target", hold_count);
+        info!("drop {} target", drop_count);
+        self.held_bytes.set(hold_bytes);
+        self.held_count.set(hold_count);
+
+        let head = self.head.deref();
+        that.unwrap_unchecked()
+            .as_ref()
+            .info
+            .prev
+            .set(NonNullGcBox::from_ptr(head));
+        head.info.next.set(that);
+
+        if self.record_dropped.get() {
+            let mut dropped = self.last_gc_dropped.borrow_mut();
+            let mut garbage = garbage_head;
+            while let Some(node) = garbage {
+                let n = node.as_ref();
+                garbage = n.info.next.get();
+                dropped.push((node.ptr.as_ptr() as usize, (*n.value()).type_name()));
+            }
+        }
+        if self.lazy_sweep.get() {
+            self.pending_values.set(garbage_head);
+            self.pending_count.set(drop_count);
+            return (hold_count, drop_count);
+        }
+
+        if self.inject_fault(FaultPoint::DuringSweep) {
+            panic!("injected panic during sweep");
+        }
+
+        // Drop every value before freeing any box, a value may still hold
+        // handles to other garbage.
+        let mut garbage = garbage_head;
+        while let Some(node) = garbage {
+            garbage = node.as_ref().info.next.get();
+            GcBox::drop_value(&mut *node.as_ptr().cast_mut());
+        }
+        let mut garbage = garbage_head;
+        while let Some(node) = garbage {
+            garbage = node.as_ref().info.next.get();
+            self.free_box(node);
+        }
+
+        (hold_count, drop_count)
+    }
+}
+
+impl<'c> Drop for GcContextRaw<'c> {
+    fn drop(&mut self) {
+        info!("drop gc");
+        drop(self.post_gc_hooks.take());
+        if let Some(suspended) = self.suspended_gc.take() {
+            drop(IncrementalGc::resume(self, suspended));
+        }
+        self.unfreeze();
+        self.gc();
+        let iter = GcNodeBackIter::steal(self);
+        let mut leak_count = 0;
+        let mut leak_names = Vec::new();
+        #[cfg(feature = "alloc-tracking")]
+        let mut leak_sites = std::collections::BTreeMap::<_, usize>::new();
+        for node in iter {
+            trace!("leak {:?}", node.as_ptr());
+            leak_count += 1;
+            let n = unsafe { node.as_ref() };
+            #[cfg(feature = "alloc-tracking")]
+            if let Some(location) = n.info.location.get() {
+                *leak_sites.entry(location).or_default() += 1;
+            }
+            if self.panic_on_leak.get() {
+                leak_names.push(unsafe { (*n.value()).type_name() });
+            }
+            debug_assert!(n.info.state.get() == GcState::Active);
+            n.info.prev.set(None);
+            n.info.next.set(None);
+            n.info.root.set(n.info.root.get() + 1);
+        }
+        if leak_count != 0 {
+            warn!("leak {} target", leak_count);
+            #[cfg(feature = "alloc-tracking")]
+            for (location, count) in leak_sites {
+                warn!("leak {} target allocated at {}", count, location);
+            }
+        }
+        #[cfg(feature = "pooling")]
+        self.compact_free_list();
+        #[cfg(feature = "background-free")]
+        if let Some(free_thread) = self.free_thread.take() {
+            free_thread.join();
+        }
+        if leak_count != 0 && self.panic_on_leak.get() && !std::thread::panicking() {
+            panic!("leak {} target: {:?}", leak_count, leak_names);
+        }
+    }
+}
+
+impl<'c> Debug for GcContextRaw<'c> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GcContextRaw")
+            .field("auto_gc", &self.auto_gc.get())
+            .field("alloc_count", &self.alloc_count.get())
+            .field("auto_gc_bytes", &self.auto_gc_bytes.get())
+            .field("alloc_bytes", &self.alloc_bytes.get())
+            .field("last_gc_duration", &self.last_gc_duration.get())
+            .field("total_gc_duration", &self.total_gc_duration.get())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct GcContext<'c> {
+    inner: GcContextRaw<'static>,
+    marker: PhantomData<*mut &'c ()>,
+}
+
+impl<'c> GcContext<'c> {
+    fn inner(&self) -> &GcContextRaw<'c> {
+        unsafe { &*((&self.inner as *const GcContextRaw<'static>).cast()) }
+    }
+
+    pub fn new() -> Self {
+        Self {
+            inner: GcContextRaw::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Creates an empty context with the same settings, finalizers and gc trigger.
+    pub fn clone_config<'d>(&self) -> GcContext<'d> {
+        let r = GcContext::new();
+        let (src, dst) = (&self.inner, &r.inner);
+        dst.auto_gc.set(src.auto_gc.get());
+        dst.auto_gc_bytes.set(src.auto_gc_bytes.get());
+        dst.min_heap_before_gc.set(src.min_heap_before_gc.get());
+        dst.gc_trigger.replace(src.gc_trigger.borrow().clone());
+        dst.record_dropped.set(src.record_dropped.get());
+        dst.panic_on_leak.set(src.panic_on_leak.get());
+        dst.drop_order.set(src.drop_order.get());
+        dst.trace_depth_limit.set(src.trace_depth_limit.get());
+        dst.type_finalizers
+            .replace(src.type_finalizers.borrow().clone());
+        dst.trace_profiling.set(src.trace_profiling.get());
+        dst.stress.set(src.stress.get());
+        r
+    }
+
+    pub fn set_auto_gc(&self, auto_gc: usize) {
+        self.inner().set_auto_gc(auto_gc);
+    }
+
+    /// Suppresses auto gc until the live set first exceeds `n` objects, then the usual
+    /// thresholds apply for good.
+    ///
+    /// The live set is estimated like `GcStats::estimated_live_objects`, so objects freed by
+    /// reference counting since the last collection still count towards `n`.
+    pub fn set_min_heap_before_gc(&self, n: usize) {
+        let inner = self.inner();
+        inner.min_heap_before_gc.set(n);
+        inner.warmed_up.set(false);
+    }
+
+    /// Runs a collection after an allocation whenever `trigger` returns `true`, in addition
+    /// to the count and bytes thresholds.
+    ///
+    /// `alloc_batch` evaluates the trigger once after the whole batch.
+    pub fn set_gc_trigger(&self, trigger: Box<dyn Fn(&GcStats) -> bool>) {
+        *self.inner().gc_trigger.borrow_mut() = Some(Rc::from(trigger));
+    }
+
+    pub fn clear_gc_trigger(&self) {
+        *self.inner().gc_trigger.borrow_mut() = None;
+    }
+
+    /// Calls `hook` after every collection, including incremental and aborted ones, until it
+    /// returns `false`.
+    ///
+    /// Hooks run once the context is back to normal, so they may drop handles and allocate.
+    /// They are skipped while a collection unwinds and dropped without running when the
+    /// context is dropped.
+    pub fn add_post_gc_hook(&self, hook: Box<dyn FnMut() -> bool + 'c>) {
+        self.inner().post_gc_hooks.borrow_mut().push(hook);
+    }
+
+    /// Fails at every [`FaultPoint`] where `injector` returns `true`, to test recovery from
+    /// allocation failures and panics in a collection.
+    #[cfg(feature = "testing")]
+    pub fn set_fault_injector(&self, injector: Box<dyn Fn(FaultPoint) -> bool>) {
+        *self.inner().fault_injector.borrow_mut() = Some(Rc::from(injector));
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn clear_fault_injector(&self) {
+        *self.inner().fault_injector.borrow_mut() = None;
+    }
+
+    /// Returns the current heap statistics.
+    pub fn stats(&self) -> GcStats {
+        self.inner().stats()
+    }
+
+    /// Runs a collection before every allocation, to expose objects that are not rooted or
+    /// traced properly. Collections are slow, only use it in tests.
+    pub fn set_stress(&self, enabled: bool) {
+        self.inner().stress.set(enabled);
+    }
+
+    /// Suspends automatic collections, including stress mode and the trigger set by
+    /// `set_gc_trigger`, until every guard is dropped.
+    ///
+    /// The configuration is left untouched, so it can still be changed while paused.
+    pub fn pause_gc(&self) -> GcPauseGuard<'_, 'c> {
+        let inner = self.inner();
+        inner.paused.set(inner.paused.get() + 1);
+        GcPauseGuard { context: inner }
+    }
+
+    /// Runs a collection from `alloc` once the boxes allocated since the last
+    /// collection exceed `auto_gc_bytes`, `0` disables it.
+    pub fn set_auto_gc_bytes(&self, auto_gc_bytes: usize) {
+        self.inner().set_auto_gc_bytes(auto_gc_bytes);
+    }
+
+    /// Bytes held after the last collection plus bytes allocated since.
+    ///
+    /// Objects freed by reference counting are not subtracted until the next collection.
+    pub fn estimated_heap_bytes(&self) -> usize {
+        let inner = self.inner();
+        inner.held_bytes.get() + inner.alloc_bytes.get()
+    }
+
+    #[track_caller]
+    pub fn alloc<T: GcTarget<'c> + 'c>(&'c self, value: T) -> GcRoot<'c, T> {
+        self.inner().alloc(value)
+    }
+
+    /// Like `alloc`, but gives `value` back instead of aborting if the allocation fails.
+    #[track_caller]
+    pub fn try_alloc<T: GcTarget<'c> + 'c>(
+        &'c self,
+        value: T,
+    ) -> Result<GcRoot<'c, T>, (T, GcAllocError)> {
+        self.inner()
+            .try_alloc(value)
+            .map_err(|value| (value, GcAllocError))
+    }
+
+    /// Allocates `value` in a `GcMut`, a mutable field without borrow guards.
+    #[track_caller]
+    pub fn alloc_mut<T: GcTarget<'c> + 'c>(&'c self, value: T) -> GcRoot<'c, GcMut<'c, T>> {
+        self.alloc(GcMut::new(value))
+    }
+
+    /// Allocates `value`, recording `location` as its allocation site instead of the caller.
+    #[cfg(feature = "alloc-tracking")]
+    pub fn alloc_tracked<T: GcTarget<'c> + 'c>(
+        &'c self,
+        value: T,
+        location: &'static Location<'static>,
+    ) -> GcRoot<'c, T> {
+        let r = self.alloc(value);
+        unsafe { r.ptr.as_ref() }.info.location.set(Some(location));
+        r
+    }
+
+    /// Allocates the value returned by `f`, which gets a handle to the object being built.
+    ///
+    /// The handle may be cloned and stored in the value, but `upgrade` returns `None` until `f`
+    /// returns. If `f` panics the allocation is leaked.
+    #[track_caller]
+    pub fn alloc_cyclic_typed<T: GcTarget<'c> + 'c>(
+        &'c self,
+        f: impl FnOnce(&GcObject<'c, T>) -> T,
+    ) -> GcRoot<'c, T> {
+        self.inner().alloc_cyclic_typed(f)
+    }
+
+    /// Allocates `value` and returns a root together with a handle, the root keeps the object
+    /// alive until the handle is stored somewhere reachable.
+    #[track_caller]
+    pub fn alloc_and_downgrade<T: GcTarget<'c> + 'c>(
+        &'c self,
+        value: T,
+    ) -> (GcRoot<'c, T>, GcObject<'c, T>) {
+        let root = self.alloc(value);
+        let obj = root.downgrade();
+        (root, obj)
+    }
+
+    /// Allocates a copy of `s`.
+    ///
+    /// Gc objects must be sized to carry their vtable, so the string is boxed inside the object.
+    #[track_caller]
+    pub fn alloc_str(&'c self, s: &str) -> GcRoot<'c, Box<str>> {
+        self.alloc(Box::from(s))
+    }
+
+    /// Allocates a copy of `s`, boxed inside the object like [`GcContext::alloc_str`].
+    #[track_caller]
+    pub fn alloc_slice_copy<T: GcTarget<'c> + Copy + 'c>(
+        &'c self,
+        s: &[T],
+    ) -> GcRoot<'c, Box<[T]>> {
+        self.alloc(Box::from(s))
+    }
+
+    /// Allocates every item with a single auto gc check up front.
+    ///
+    /// No collection runs while the batch is allocated, so items allocated earlier can not be
+    /// collected before the later ones are linked to them. The batch still counts towards the
+    /// auto gc thresholds, so the next `alloc` may collect.
+    #[track_caller]
+    pub fn alloc_batch<T: GcTarget<'c> + 'c, I: IntoIterator<Item = T>>(
+        &'c self,
+        items: I,
+    ) -> Vec<GcRoot<'c, T>> {
+        self.inner().alloc_batch(items)
+    }
+
+    /// Replaces the value of `obj` with `new` and returns the old value.
+    ///
+    /// Other roots of the object may have handed out references to the value, so the swap is
+    /// refused and `new` is given back as `Err` unless `obj` is the only root.
+    pub fn swap<T: GcTarget<'c> + 'c>(&self, obj: &mut GcRoot<'c, T>, new: T) -> Result<T, T> {
+        unsafe {
+            let node = obj.ptr.as_mut();
+            self.inner().debug_check_owner(&node.info);
+            if node.info.root.get() != 1 {
+                return Err(new);
+            }
+            Ok(replace(node.value.deref_mut(), new))
+        }
+    }
+
+    /// Creates a weak handle to `obj`, cleared by the collection that frees the object.
+    pub fn downgrade_weak<T: GcTarget<'c> + ?Sized + 'c>(
+        &self,
+        obj: &GcRoot<'c, T>,
+    ) -> GcWeak<'c, T> {
+        let inner = self.inner();
+        unsafe {
+            let node = obj.ptr.as_ref();
+            inner.debug_check_owner(&node.info);
+            node.info.count.set(node.info.count.get() + 1);
+        }
+        let slot = Rc::new(GcWeakSlot(Cell::new(Some(NonNullGcBox::from_non_null(
+            obj.ptr,
+        )))));
+        inner.weak_slots.borrow_mut().push(Rc::downgrade(&slot));
+        GcWeak { ptr: obj.ptr, slot }
+    }
+
+    /// Creates an ephemeron that keeps `value` alive only while `key` is reachable.
+    pub fn ephemeron<K: GcTarget<'c> + ?Sized + 'c, V: GcTarget<'c> + ?Sized + 'c>(
+        &self,
+        key: &GcRoot<'c, K>,
+        value: &GcRoot<'c, V>,
+    ) -> GcEphemeron<'c, K, V> {
+        let inner = self.inner();
+        unsafe {
+            for node in [&key.ptr.as_ref().info, &value.ptr.as_ref().info] {
+                inner.debug_check_owner(node);
+                node.count.set(node.count.get() + 1);
+            }
+        }
+        let slot = Rc::new(GcEphemeronSlot {
+            key: Cell::new(Some(NonNullGcBox::from_non_null(key.ptr))),
+            value: Cell::new(Some(NonNullGcBox::from_non_null(value.ptr))),
+        });
+        inner.ephemerons.borrow_mut().push(Rc::downgrade(&slot));
+        GcEphemeron {
+            key: key.ptr,
+            value: value.ptr,
+            slot,
+        }
+    }
+
+    /// Releases the blocks kept for reuse by the `pooling` feature.
+    ///
+    /// Collections keep up to a few thousand freed blocks per layout, and `alloc` reuses them
+    /// before asking the allocator.
+    #[cfg(feature = "pooling")]
+    pub fn compact_free_list(&self) {
+        self.inner().compact_free_list();
+    }
+
+    /// Like `try_gc`, but the memory of the garbage is released by a background thread.
+    ///
+    /// Values are still dropped by the caller before this returns, since dropping the handles
+    /// they hold updates counts that are not atomic, so no `Send` bound is needed. Only the
+    /// deallocation moves off the pause, which helps heaps of many small objects most. The
+    /// thread is spawned by the first call and joined when the context is dropped.
+    #[cfg(feature = "background-free")]
+    pub fn collect_background(&self) -> Option<GcCollectReport> {
+        self.inner().collect_background()
+    }
+
+    /// Deep clones the graph reachable from `src` into this context.
+    ///
+    /// Returns `None` if `src` was already collected.
+    pub fn import<'s, T: GcDeepClone<'s, 'c>>(
+        &'c self,
+        src: &GcObject<'s, T>,
+    ) -> Option<GcRoot<'c, T::Target>> {
+        GcCloneMap::new().clone_object(src, self)
+    }
+
+    /// Drops every object, rooted or not, keeping the configuration.
+    ///
+    /// Frozen objects and objects kept alive by `GcRoot::leak` are dropped too.
+    ///
+    /// # Safety
+    ///
+    /// No handle to an object of this context may be used or dropped afterwards, every
+    /// handle must already be dropped or forgotten.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub unsafe fn clear(&self) {
+        let inner = self.inner();
+        assert_eq!(
+            inner.state.get(),
+            GcContextState::Normal,
+            "clear during collection"
+        );
+        info!("clear gc");
+        let start = Instant::now();
+        inner.state.set(GcContextState::Gc);
+        inner.begin_gc();
+        inner.unfreeze();
+        let iter = GcNodeBackIter::steal(inner);
+        for node in iter.clone() {
+            node.as_ref().info.state.set(GcState::Untracked);
+        }
+        inner.sweep(iter, false);
+        inner.alloc_count.set(0);
+        inner.end_gc(start.elapsed());
+    }
+
+    /// Registers `f` to run on every unreachable `T` when finalizers run, after its own
+    /// `GcTarget::finalize`, replacing any previous finalizer for `T`.
+    ///
+    /// `T` must override `GcTarget::as_any`, as `#[derive(Trace)]` does with `#[trace(any)]`
+    /// and `trace_none!` with `@any`, otherwise `f` is never called. A foreign type can't be
+    /// given `as_any`, so wrap it in a local newtype. A warning is logged for live or finalized
+    /// objects named like `T` that don't override `as_any`.
+    pub fn set_type_finalizer<T: 'static>(&self, f: Box<dyn Fn(&T)>) {
+        let inner = self.inner();
+        let name = type_name::<T>();
+        for node in GcNodeIter::new(inner).chain(GcNodeIter::frozen(inner)) {
+            let value = unsafe { &*node.as_ref().value() };
+            if value.as_any().is_none() && value.type_name() == name {
+                warn!(
+                    "{} does not override as_any, its type finalizer never runs",
+                    name
+                );
+                break;
+            }
+        }
+        let f = Rc::new(move |any: &dyn Any| {
+            if let Some(value) = any.downcast_ref::<T>() {
+                f(value);
+            }
+        });
+        inner
+            .type_finalizers
+            .borrow_mut()
+            .insert(TypeId::of::<T>(), (name, f));
+    }
+
+    /// Runs finalizers of unreachable objects, then traces again until no new
+    /// finalizer runs, so that resurrected objects are not freed.
+    pub fn collect_to_completion_with_finalizers(&self) -> GcCollectReport {
+        self.inner().collect(true, None).unwrap_or_default()
+    }
+
+    /// Collects with finalizers until a pass drops nothing.
+    ///
+    /// Drops and finalizers may release more objects, each pass reclaims those left by the
+    /// previous one. The returned report sums the dropped objects of all passes.
+    pub fn force_collect_until_stable(&self) -> GcCollectReport {
+        let mut report = GcCollectReport::default();
+        loop {
+            let Some(pass) = self.inner().collect(true, None) else {
+                return report;
+            };
+            report.traced = pass.traced;
+            report.held = pass.held;
+            report.dropped += pass.dropped;
+            if pass.dropped == 0 {
+                return report;
+            }
+        }
+    }
+
+    /// Hook for platform memory warnings, runs `force_collect_until_stable`.
+    pub fn collect_on_pressure(&self) -> GcCollectReport {
+        info!("collect on memory pressure");
+        self.force_collect_until_stable()
+    }
+
+    /// Starts a collection that is driven with [`IncrementalGc::step`].
+    pub fn start_incremental(&self) -> IncrementalGc<'_, 'c> {
+        IncrementalGc::new(self.inner())
+    }
+
+    /// Drives an incremental collection until `deadline` or until it completes, returning
+    /// whether it completed.
+    ///
+    /// An unfinished collection is kept in the context and continued by the next call, other
+    /// collections do not run meanwhile. The clock is read every few objects and the final
+    /// sweep is not bounded by the deadline.
+    pub fn try_incremental_during(&self, deadline: Instant) -> bool {
+        const CLOCK_INTERVAL: usize = 64;
+
+        let inner = self.inner();
+        let mut gc = match inner.suspended_gc.take() {
+            Some(suspended) => IncrementalGc::resume(inner, suspended),
+            None if inner.state.get() == GcContextState::Normal => IncrementalGc::new(inner),
+            None => return false,
+        };
+        while Instant::now() < deadline {
+            if let GcProgress::Done(_) = gc.step(CLOCK_INTERVAL) {
+                return true;
+            }
+        }
+        match gc.suspend() {
+            Some(suspended) => {
+                inner.suspended_gc.set(Some(suspended));
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Allocates `value` and pins it.
+    ///
+    /// Every object lives in its own heap allocation that is never moved by a
+    /// collection, so its address stays fixed until the value is dropped. A
+    /// root therefore already upholds the [`Pin`] contract.
+    #[track_caller]
+    pub fn alloc_pinned<T: GcTarget<'c> + 'c>(&'c self, value: T) -> Pin<GcRoot<'c, T>> {
+        unsafe { Pin::new_unchecked(self.alloc(value)) }
+    }
+
+    /// Marks like `gc`, but sets the unreachable objects aside instead of dropping them, and
+    /// returns how many were set aside.
+    ///
+    /// The objects are dropped by [`GcContext::sweep_step`], or before the next collection
+    /// starts. Handles to them can not be upgraded in the meantime.
+    pub fn mark_only(&self) -> usize {
+        let inner = self.inner();
+        inner.lazy_sweep.set(true);
+        let report = inner.collect(false, None);
+        inner.lazy_sweep.set(false);
+        report.map_or(0, |report| report.dropped)
+    }
+
+    /// Drops up to `max` objects set aside by [`GcContext::mark_only`], returns how many.
+    ///
+    /// Memory is released once every object set aside was dropped, since a value may hold
+    /// handles to the others. Does nothing during a collection.
+    pub fn sweep_step(&self, max: usize) -> usize {
+        let inner = self.inner();
+        if inner.state.get() != GcContextState::Normal {
+            return 0;
+        }
+        inner.state.set(GcContextState::Gc);
+        let count = unsafe { inner.sweep_pending(max) };
+        inner.state.set(GcContextState::Normal);
+        count
+    }
+
+    /// Number of objects set aside by [`GcContext::mark_only`] and not dropped yet.
+    pub fn pending_sweep(&self) -> usize {
+        self.inner().pending_count.get()
+    }
+
+    pub fn gc(&self) {
+        self.inner().gc()
+    }
+
+    /// Like `gc`, but returns `None` instead of doing nothing when a collection is already
+    /// running, for example when called from a `Drop` or a finalizer.
+    pub fn try_gc(&self) -> Option<GcCollectReport> {
+        self.inner().collect(false, None)
+    }
+
+    /// Runs a collection that also treats every word in `stack_bottom..stack_top` that holds
+    /// the address of a box as a root.
+    ///
+    /// The addresses may be given in either order. Only word aligned slots are scanned.
+    ///
+    /// # Safety
+    ///
+    /// The whole range must be readable and initialized for the duration of the call, reading
+    /// an uninitialized slot as a word is undefined behaviour.
+    pub unsafe fn gc_conservative(
+        &self,
+        stack_bottom: *const u8,
+        stack_top: *const u8,
+    ) -> Option<GcCollectReport> {
+        let (low, high) = if stack_bottom <= stack_top {
+            (stack_bottom, stack_top)
+        } else {
+            (stack_top, stack_bottom)
+        };
+        let start = low.add(low.align_offset(align_of::<usize>()));
+        let len = if start < high {
+            (high as usize - start as usize) / size_of::<usize>()
+        } else {
+            0
+        };
+        self.inner()
+            .collect(false, Some((start.cast::<usize>(), len)))
+    }
+
+    pub fn last_gc_duration(&self) -> Duration {
+        self.inner().last_gc_duration.get()
+    }
+
+    pub fn total_gc_duration(&self) -> Duration {
+        self.inner().total_gc_duration.get()
+    }
+
+    /// Number of collections run so far, including incremental and aborted ones.
+    pub fn gc_count(&self) -> usize {
+        self.inner().gc_count.get()
+    }
+
+    /// Number of objects allocated so far, including freed ones.
+    pub fn total_allocs(&self) -> usize {
+        self.inner().alloc_total.get()
+    }
+
+    /// Zeros the collection timings, `gc_count` and `total_allocs`, to measure one phase of a
+    /// program.
+    ///
+    /// The heap, the configuration and the counters that schedule automatic collections are
+    /// left unchanged.
+    pub fn reset_stats(&self) {
+        let inner = self.inner();
+        inner.last_gc_duration.set(Duration::ZERO);
+        inner.total_gc_duration.set(Duration::ZERO);
+        inner.gc_count.set(0);
+        inner.alloc_total.set(0);
+    }
+
+    /// Records the address and type name of every live object.
+    ///
+    /// With the `alloc-tracking` feature, the allocation sites are recorded too.
+    pub fn snapshot(&self) -> HeapSnapshot {
+        let inner = self.inner();
+        let snapshot = HeapSnapshot::new(
+            GcNodeIter::new(inner)
+                .chain(GcNodeIter::frozen(inner))
+                .map(|node| {
+                    let name = unsafe { (*node.as_ref().value()).type_name() };
+                    (node.ptr.as_ptr() as usize, name)
+                })
+                .collect(),
+        );
+        #[cfg(feature = "alloc-tracking")]
+        let snapshot = snapshot.with_locations(
+            GcNodeIter::new(inner)
+                .chain(GcNodeIter::frozen(inner))
+                .filter_map(|node| {
+                    let location = unsafe { node.as_ref() }.info.location.get()?;
+                    Some((node.ptr.as_ptr() as usize, location))
+                })
+                .collect(),
+        );
+        snapshot
+    }
+
+    /// Counts the live objects by box size in bytes, header included.
+    pub fn size_histogram(&self) -> BTreeMap<usize, usize> {
+        let inner = self.inner();
+        let mut histogram = BTreeMap::new();
+        for node in GcNodeIter::new(inner).chain(GcNodeIter::frozen(inner)) {
+            *histogram
+                .entry(size_of_val(unsafe { node.as_ref() }))
+                .or_default() += 1;
+        }
+        histogram
+    }
+
+    /// Moves every live object to the frozen set.
+    ///
+    /// Frozen objects are never swept, so later collections only reclaim objects allocated
+    /// after the freeze. They are still traced on every collection because they may point to
+    /// younger objects, and are still freed by reference counting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub fn freeze_current(&self) {
+        let inner = self.inner();
+        assert_eq!(
+            inner.state.get(),
+            GcContextState::Normal,
+            "freeze during collection"
+        );
+        inner.freeze_current();
+    }
+
+    /// Moves the frozen set back to the live objects and runs a collection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub fn collect_frozen(&self) {
+        let inner = self.inner();
+        assert_eq!(
+            inner.state.get(),
+            GcContextState::Normal,
+            "unfreeze during collection"
+        );
+        inner.unfreeze();
+        inner.gc();
+    }
+
+    /// Calls `f` with a temporary handle to every rooted object.
+    pub fn for_each_root(&self, mut f: impl FnMut(&GcObjectThin<'c>)) {
+        let inner = self.inner();
+        for (head, tail) in [
+            (&inner.head, &inner.tail),
+            (&inner.frozen_head, &inner.frozen_tail),
+        ] {
+            let tail = NonNullGcBox::from_ptr(tail.deref());
+            let mut node = head.info.next.get();
+            // The current object is rooted before the previous one is released, since dropping
+            // the previous one may free the objects its value holds.
+            let mut previous = None;
+            while node != tail {
+                let current = unsafe { node.unwrap_unchecked() };
+                let root = unsafe { GcRootThin::from_box(current.as_non_null()) };
+                drop(previous.take());
+                if root.downgrade().root_count() > 1 {
+                    f(&root.downgrade());
+                }
+                node = unsafe { current.as_ref() }.info.next.get();
+                previous = Some(root);
+            }
+            drop(previous);
+        }
+    }
+
+    /// Returns the addresses of a chain of objects from a root to `target`, the first object of
+    /// the chain is rooted or frozen, or `None` if `target` is unreachable.
+    ///
+    /// Each object is linked to the object it was first reached from, so the chain is one
+    /// retention path among possibly many. The addresses match [`HeapSnapshot`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub fn retention_path(&self, target: &GcObjectThin<'c>) -> Option<Vec<usize>> {
+        self.inner().retention_path(target.ptr)
+    }
+
+    /// Collects until stable and panics if the target of `weak` is still alive, reporting its
+    /// type and the addresses of a chain of objects from a root that keep it alive.
+    ///
+    /// Takes a [`GcWeak`] since a [`GcObjectThin`] held outside the heap dangles once its
+    /// object is freed.
+    #[cfg(feature = "testing")]
+    #[track_caller]
+    pub fn assert_collected<T: GcTarget<'c> + ?Sized + 'c>(&self, weak: &GcWeak<'c, T>) {
+        self.force_collect_until_stable();
+        if !weak.is_live() {
+            return;
+        }
+        let node = NonNullGcBox::from_non_null(weak.ptr);
+        let name = unsafe { (*node.as_ref().value()).type_name() };
+        let path = self.inner().retention_path(node);
+        panic!(
+            "{} at {:#x} was not collected, retained by {:x?}",
+            name,
+            node.ptr.as_ptr() as usize,
+            path
+        );
+    }
+
+    /// Calls `f` with the addresses of both ends of every edge between live objects.
+    ///
+    /// Edges are what each object's `trace` accepts, so ephemerons are not included. The
+    /// addresses match [`HeapSnapshot`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub fn edges(&self, mut f: impl FnMut(usize, usize)) {
+        let inner = self.inner();
+        assert_eq!(
+            inner.state.get(),
+            GcContextState::Normal,
+            "edges during collection"
+        );
+        let roots = GcNodeIter::new(inner)
+            .chain(GcNodeIter::frozen(inner))
+            .map(|node| unsafe { GcRootThin::from_box(node.as_non_null()) })
+            .collect::<Vec<_>>();
+        let mut token = GcTraceToken::new(inner.trace_depth_limit.get());
+        token.edges = Some(Vec::new());
+        for root in &roots {
+            let from = root.ptr.ptr.as_ptr() as usize;
+            // An `Rc` shared by several objects is an edge of each of them.
+            token.shared.clear();
+            unsafe { token.trace_node(root.ptr) };
+            for to in token.edges.as_mut().unwrap().drain(..) {
+                f(from, to);
+            }
+        }
+    }
+
+    /// Returns `root` and every object reachable from it, in breadth first order.
+    ///
+    /// Other roots are ignored and no collection state is touched. The handles are not traced,
+    /// so they dangle once a collection frees their objects; store them in the heap or upgrade
+    /// them first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub fn reachable_from(&self, root: &GcObjectThin<'c>) -> Vec<GcObjectThin<'c>> {
+        let inner = self.inner();
+        assert_eq!(
+            inner.state.get(),
+            GcContextState::Normal,
+            "reachable from during collection"
+        );
+        let Some(root) = root.upgrade() else {
+            return Vec::new();
+        };
+        let mut seen = HashSet::from([root.ptr]);
+        let mut found = vec![root];
+        let mut token = GcTraceToken::new(inner.trace_depth_limit.get());
+        token.edges = Some(Vec::new());
+        let mut i = 0;
+        while let Some(node) = found.get(i).map(|root| root.ptr) {
+            unsafe { token.trace_node(node) };
+            for to in token.edges.as_mut().unwrap().drain(..) {
+                let to = unsafe { GcObjectThin::from_raw(to as *const ()).unwrap_unchecked() };
+                // objects still being constructed can't be upgraded and have no edges yet
+                if seen.insert(to.ptr) {
+                    if let Some(to) = to.upgrade() {
+                        found.push(to);
+                    }
+                }
+            }
+            i += 1;
+        }
+        found.iter().map(GcRootThin::downgrade).collect()
+    }
+
+    /// Calls `f` with the value of every live object.
+    ///
+    /// Every object is rooted for the duration of the walk, so `f` may freely drop handles.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub fn inspect_heap(&self, mut f: impl FnMut(&dyn GcTarget<'c>)) {
+        let inner = self.inner();
+        assert_eq!(
+            inner.state.get(),
+            GcContextState::Normal,
+            "inspect heap during collection"
+        );
+        let roots = GcNodeIter::new(inner)
+            .chain(GcNodeIter::frozen(inner))
+            .map(|node| unsafe { GcRootThin::from_box(node.as_non_null()) })
+            .collect::<Vec<_>>();
+        for root in &roots {
+            f(&**root);
+        }
+    }
+
+    /// Panics in `GcTraceToken::trace_nested` once values nest deeper than `limit` while
+    /// tracing one object, `0` disables the check.
+    ///
+    /// The `Box`, `Rc` and `Arc` impls trace through `trace_nested`, so a runaway chain reports
+    /// a clear error instead of overflowing the stack.
+    pub fn set_trace_depth_limit(&self, limit: usize) {
+        self.inner().trace_depth_limit.set(limit);
+    }
+
+    /// Panics when the context is dropped with objects still alive, listing their type names.
+    pub fn panic_on_leak(&self, enabled: bool) {
+        self.inner().panic_on_leak.set(enabled);
+    }
+
+    /// Sets the order in which collections drop unreachable objects.
+    ///
+    /// Objects freed by reference counting are always dropped immediately.
+    pub fn set_drop_order(&self, drop_order: DropOrder) {
+        self.inner().drop_order.set(drop_order);
+    }
+
+    /// Records the address and type name of every object dropped by a collection.
+    pub fn set_record_dropped(&self, record_dropped: bool) {
+        self.inner().record_dropped.set(record_dropped);
+    }
+
+    /// Counts the objects traced by each collection per type name.
+    pub fn set_trace_profiling(&self, enabled: bool) {
+        self.inner().trace_profiling.set(enabled);
+    }
+
+    /// Returns how many objects of each type the last profiled collection traced, most first.
+    ///
+    /// Objects traced again by the final step of an incremental collection are counted twice.
+    pub fn last_trace_profile(&self) -> Vec<(&'static str, usize)> {
+        self.inner().last_trace_profile.borrow().clone()
+    }
+
+    pub fn take_last_gc_dropped(&self) -> Vec<(usize, &'static str)> {
+        self.inner().last_gc_dropped.take()
+    }
+
+    /// Collects weak handles to every live object of type `T`.
+    ///
+    /// Only types whose [`GcTarget::as_any`] returns `Some` can be found, which
+    /// `#[derive(Trace)]` provides with `#[trace(any)]`.
+    pub fn objects_of_type<T: GcTarget<'c> + 'static>(&self) -> Vec<GcObject<'c, T>> {
+        let mut r = Vec::new();
+        let inner = self.inner();
+        for node in GcNodeIter::new(inner).chain(GcNodeIter::frozen(inner)) {
+            let n = unsafe { node.as_ref() };
+            if n.is::<T>() {
+                unsafe { r.push(GcObject::from_box(node.ptr.cast::<GcBox<'c, T>>())) };
+            }
+        }
+        r
+    }
+}
+
+impl<'c> Default for GcContext<'c> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Disables auto gc until dropped, guards may be nested and dropped in any order.
+pub struct GcPauseGuard<'s, 'c> {
+    context: &'s GcContextRaw<'c>,
+}
+
+impl<'s, 'c> GcPauseGuard<'s, 'c> {
+    /// Releases the pause and runs a collection immediately.
+    pub fn resume(self) {
+        let context = self.context;
+        drop(self);
+        context.gc();
+    }
+}
+
+impl<'s, 'c> Drop for GcPauseGuard<'s, 'c> {
+    fn drop(&mut self) {
+        self.context.paused.set(self.context.paused.get() - 1);
+    }
+}
+
+impl<'s, 'c> Debug for GcPauseGuard<'s, 'c> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GcPauseGuard")
+            .field("paused", &self.context.paused.get())
+            .finish()
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum GcProgress {
+    InProgress,
+    Done(GcCollectReport),
+}
+
+/// A collection driven step by step.
+///
+/// The mutator may run between steps. Because handles can be moved between
+/// objects without notifying the collector, the final step traces every marked
+/// object again, plus everything allocated since the start, before sweeping.
+/// Dropping an unfinished collection frees nothing.
+pub struct IncrementalGc<'s, 'c> {
+    context: &'s GcContextRaw<'c>,
+    iter: Option<GcNodeBackIter<'c>>,
+    token: GcTraceToken<'c>,
+    report: GcCollectReport,
+    elapsed: Duration,
+}
+
+/// An incremental collection stored in the context between calls.
+struct SuspendedGc<'c> {
+    iter: GcNodeBackIter<'c>,
+    token: GcTraceToken<'c>,
+    report: GcCollectReport,
+    elapsed: Duration,
+}
+
+impl<'s, 'c> IncrementalGc<'s, 'c> {
+    fn resume(context: &'s GcContextRaw<'c>, suspended: SuspendedGc<'c>) -> Self {
+        Self {
+            context,
+            iter: Some(suspended.iter),
+            token: suspended.token,
+            report: suspended.report,
+            elapsed: suspended.elapsed,
+        }
+    }
+
+    fn suspend(mut self) -> Option<SuspendedGc<'c>> {
+        let iter = self.iter.take()?;
+        Some(SuspendedGc {
+            iter,
+            token: replace(&mut self.token, GcTraceToken::new(0)),
+            report: self.report,
+            elapsed: self.elapsed,
+        })
+    }
+
+    fn new(context: &'s GcContextRaw<'c>) -> Self {
+        let mut r = Self {
+            context,
+            iter: None,
+            token: GcTraceToken::for_context(context),
+            report: GcCollectReport::default(),
+            elapsed: Duration::ZERO,
+        };
+        if context.state.get() != GcContextState::Normal {
+            info!("gc busy");
+            return r;
+        }
+        let start = Instant::now();
+        info!("begin incremental gc");
+        context.begin_gc();
+        let iter = GcNodeBackIter::steal(context);
+        if iter.is_empty() {
+            context.end_gc(start.elapsed());
+            return r;
+        }
+        context.state.set(GcContextState::Incremental);
+        r.report.traced =
+            unsafe { GcContextRaw::mark_roots(&iter, &mut r.token, GcState::Pending) };
+        r.iter = Some(iter);
+        r.elapsed = start.elapsed();
+        r
+    }
+
+    /// Traces up to `work_units` objects, sweeping once nothing is left to trace.
+    pub fn step(&mut self, work_units: usize) -> GcProgress {
+        if self.iter.is_none() {
+            return GcProgress::Done(self.report);
+        }
+        let start = Instant::now();
+        for _ in 0..work_units {
+            match unsafe { self.token.pop() } {
+                Some(node) => unsafe { self.token.trace_node(node) },
+                None => break,
+            }
+        }
+        self.end_step(start)
+    }
+
+    /// Traces objects until `budget` elapses, sweeping once nothing is left to trace.
+    ///
+    /// The clock is read every few objects, and the final sweep is not bounded by the budget.
+    pub fn step_for(&mut self, budget: Duration) -> GcProgress {
+        const CLOCK_INTERVAL: usize = 64;
+
+        if self.iter.is_none() {
+            return GcProgress::Done(self.report);
+        }
+        let start = Instant::now();
+        'budget: while start.elapsed() < budget {
+            for _ in 0..CLOCK_INTERVAL {
+                match unsafe { self.token.pop() } {
+                    Some(node) => unsafe { self.token.trace_node(node) },
+                    None => break 'budget,
+                }
+            }
+        }
+        self.end_step(start)
+    }
+
+    fn end_step(&mut self, start: Instant) -> GcProgress {
+        if self.token.head.is_none() {
+            self.finish(start);
+            GcProgress::Done(self.report)
+        } else {
+            // The mutator may move edges into a visited `Rc` before the next step.
+            self.token.shared.clear();
+            self.elapsed += start.elapsed();
+            GcProgress::InProgress
+        }
+    }
+
+    fn finish(&mut self, start: Instant) {
+        let Some(iter) = self.iter.take() else {
+            return;
+        };
+        let context = self.context;
+        context.state.set(GcContextState::Gc);
+        unsafe {
+            let token = &mut self.token;
+            token.head = None;
+            token.len = 0;
+            token.incomplete = false;
+            token.shared.clear();
+            for node in iter.clone() {
+                let n = node.as_ref();
+                if n.info.state.get() == GcState::Tracked || n.info.root.get() != 0 {
+                    n.info.state.set(GcState::Tracked);
+                    token.push(node);
+                }
+            }
+            for node in GcNodeIter::new(context) {
+                token.trace_node(node);
+            }
+            context.trace_frozen(token);
+            token.trace_all();
+            context.trace_ephemerons(token);
+            context.store_trace_profile(token);
+
+            let incomplete = token.incomplete;
+            if incomplete {
+                warn!("trace incomplete, hold all target");
+            }
+            let (held, dropped) = context.sweep(iter, incomplete);
+            self.report.held = held;
+            self.report.dropped = dropped;
+        }
+        context.end_gc(self.elapsed + start.elapsed());
+    }
+}
+
+impl<'s, 'c> Drop for IncrementalGc<'s, 'c> {
+    fn drop(&mut self) {
+        if let Some(iter) = self.iter.take() {
+            info!("abort incremental gc");
+            let start = Instant::now();
+            let context = self.context;
+            context.state.set(GcContextState::Gc);
+            unsafe { context.sweep(iter, true) };
+            context.end_gc(self.elapsed + start.elapsed());
+        }
+    }
+}
+
+impl<'s, 'c> Debug for IncrementalGc<'s, 'c> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IncrementalGc")
+            .field("worklist_len", &self.token.len)
+            .field("report", &self.report)
+            .field("elapsed", &self.elapsed)
+            .finish()
+    }
+}
+
+/// Implements a no-op `GcTarget` for types that hold no gc handles.
+///
+/// Prefix the list with `@any` to also override `as_any` and implement [`GcAny`], which
+/// requires `'static` sized types and makes them visible to downcasts, type queries and type
+/// finalizers.
+///
+/// ```
+/// enum Color {
+///     Red,
+///     Green,
+/// }
+///
+/// struct Point(i32, i32);
+///
+/// regc::trace_none!(Color);
+/// regc::trace_none!(@any Point);
+/// ```
+#[macro_export]
+macro_rules! trace_none {
+    (@any $($type:ty),+ $(,)?) => {
+        $(
+            impl<'c> $crate::GcTarget<'c> for $type {
+                fn trace(&self, token: &mut $crate::GcTraceToken<'c>) {
+                    let _ = token;
+                }
+
+                fn as_any(&self) -> ::std::option::Option<&dyn ::std::any::Any> {
+                    ::std::option::Option::Some(self)
+                }
+            }
+
+            $crate::gc_any!($type);
+        )+
+    };
+    ($($type:ty),+ $(,)?) => {
+        $(
+            impl<'c> $crate::GcTarget<'c> for $type {
+                fn trace(&self, token: &mut $crate::GcTraceToken<'c>) {
+                    let _ = token;
+                }
+            }
+        )+
+    };
+}
+
+#[test]
+fn test_trace_none() {
+    let _ = env_logger::try_init();
+    struct Name<'a>(&'a str);
+    #[allow(dead_code)]
+    struct Bytes([u8]);
+    struct Point(i32);
+    trace_none!(Name<'_>, Bytes);
+    trace_none!(@any Point);
+
+    let context = GcContext::new();
+    let name = context.alloc(Name("a"));
+    assert!((*name).as_any().is_none());
+    assert_eq!(name.0, "a");
+    let point = context.alloc(Point(1)).cast_dyn();
+    assert_eq!(point.downcast::<Point>().unwrap().0, 1);
+}
+
+#[test]
 fn test() {
     let _ = env_logger::try_init();
 
     struct Foo<'c> {
-        r: std::cell::RefCell<Option<GcObject<'c, Self>>>,
+        r: std::cell::RefCell<Option<GcObject<'c, Self>>>,
+    }
+
+    impl<'c> Drop for Foo<'c> {
+        fn drop(&mut self) {
+            println!("Drop for Foo");
+        }
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let x = context.alloc(Foo {
+        r: std::cell::RefCell::new(None),
+    });
+    *x.r.borrow_mut() = Some(x.downgrade());
+    context.gc();
+    *x.r.borrow_mut() = None;
+}
+
+#[test]
+fn test_hash_map_cycle() {
+    let _ = env_logger::try_init();
+
+    struct Node<'c> {
+        r: std::cell::RefCell<Option<GcObject<'c, Self>>>,
+        drops: std::rc::Rc<Cell<usize>>,
+    }
+
+    impl<'c> Drop for Node<'c> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    impl<'c> GcTarget<'c> for Node<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    struct Map<'c> {
+        map: std::cell::RefCell<
+            std::collections::HashMap<GcObject<'c, Node<'c>>, GcObject<'c, Node<'c>>>,
+        >,
+    }
+
+    impl<'c> GcTarget<'c> for Map<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.map.trace(token);
+        }
+    }
+
+    fn run() -> (usize, usize) {
+        let drops = std::rc::Rc::new(Cell::new(0));
+        let context = GcContext::new();
+        let map = context.alloc(Map {
+            map: std::cell::RefCell::new(std::collections::HashMap::new()),
+        });
+        let mut keys = Vec::new();
+        for _ in 0..100 {
+            let a = context.alloc(Node {
+                r: std::cell::RefCell::new(None),
+                drops: drops.clone(),
+            });
+            let b = context.alloc(Node {
+                r: std::cell::RefCell::new(Some(a.downgrade())),
+                drops: drops.clone(),
+            });
+            *a.r.borrow_mut() = Some(b.downgrade());
+            map.map.borrow_mut().insert(a.downgrade(), b.downgrade());
+            keys.push(a.downgrade());
+        }
+        context.gc();
+        let held = drops.get();
+        for key in keys.iter().step_by(2) {
+            map.map.borrow_mut().remove(key);
+        }
+        drop(keys);
+        context.gc();
+        (held, drops.get())
+    }
+
+    assert_eq!(run(), (0, 100));
+    assert_eq!(run(), (0, 100));
+}
+
+#[test]
+fn test_ref_cell_borrowed() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: std::cell::RefCell<Option<GcObject<'c, Self>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let x = context.alloc(Foo {
+        r: std::cell::RefCell::new(None),
+    });
+    let y = context.alloc(Foo {
+        r: std::cell::RefCell::new(None),
+    });
+    *y.r.borrow_mut() = Some(y.downgrade());
+    *x.r.borrow_mut() = Some(y.downgrade());
+    let y = y.downgrade();
+    let borrow = x.r.borrow_mut();
+    context.gc();
+    drop(borrow);
+    assert!(y.upgrade().is_some());
+}
+
+#[test]
+fn test_zero_sized() {
+    let _ = env_logger::try_init();
+
+    let context = GcContext::new();
+    let mut roots = (0..1000).map(|_| context.alloc(())).collect::<Vec<_>>();
+    assert!(roots
+        .iter()
+        .all(|r| r.base_ptr() != NonNull::from(&*context.inner().head).cast()));
+    assert!(roots
+        .iter()
+        .all(|r| r.base_ptr() != NonNull::from(&*context.inner().tail).cast()));
+    roots.truncate(500);
+    context.gc();
+    assert_eq!(GcNodeIter::new(context.inner()).count(), 500);
+    roots.clear();
+    context.gc();
+    assert_eq!(GcNodeIter::new(context.inner()).count(), 0);
+
+    for sentinel in [&context.inner().head, &context.inner().tail] {
+        assert_eq!(sentinel.info.state.get(), GcState::Active);
+        assert_eq!(sentinel.info.root.get(), 1);
+    }
+    let _x = context.alloc(());
+    assert_eq!(GcNodeIter::new(context.inner()).count(), 1);
+}
+
+#[test]
+fn test_boxed_slice() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        children: Box<[GcObject<'c, Foo<'c>>]>,
+        name: std::rc::Rc<str>,
+        shared_name: std::sync::Arc<str>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.children.trace(token);
+            self.name.trace(token);
+            self.shared_name.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let leaf = |name: &str| {
+        context.alloc(Foo {
+            children: Box::new([]),
+            name: name.into(),
+            shared_name: name.into(),
+        })
+    };
+    let a = leaf("a");
+    let b = leaf("b");
+    let parent = context.alloc(Foo {
+        children: Box::new([a.downgrade(), b.downgrade()]),
+        name: "parent".into(),
+        shared_name: "parent".into(),
+    });
+    drop((a, b));
+    context.gc();
+    for child in parent.children.iter() {
+        assert!(child.upgrade().is_some());
+    }
+    assert_eq!(&*parent.children[1].upgrade().unwrap().name, "b");
+}
+
+#[test]
+fn test_large_linked_list() {
+    let _ = env_logger::try_init();
+
+    struct List<'c> {
+        list: RefCell<std::collections::LinkedList<GcObject<'c, usize>>>,
+    }
+
+    impl<'c> GcTarget<'c> for List<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.list.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let list = context.alloc(List {
+        list: RefCell::new(std::collections::LinkedList::new()),
+    });
+    for i in 0..100_000 {
+        list.list
+            .borrow_mut()
+            .push_back(context.alloc(i).downgrade());
+    }
+    context.gc();
+    for (i, x) in list.list.borrow().iter().enumerate() {
+        assert_eq!(*x.upgrade().unwrap(), i);
+    }
+}
+
+#[test]
+fn test_incremental() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObject<'c, Self>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let new = || {
+        context.alloc(Foo {
+            r: RefCell::new(None),
+        })
+    };
+    let garbage = new();
+    *garbage.r.borrow_mut() = Some(garbage.downgrade());
+    drop(garbage);
+
+    let a = new();
+    let e = new();
+    *a.r.borrow_mut() = Some(e.downgrade());
+    drop(e);
+    let b = new();
+    let c = new();
+    *b.r.borrow_mut() = Some(c.downgrade());
+    let b_weak = b.downgrade();
+    let c_weak = c.downgrade();
+    drop((b, c));
+
+    let mut gc = context.start_incremental();
+    assert_eq!(gc.step(1), GcProgress::InProgress);
+    // Move the only edge to `c` into the already traced `a`.
+    *a.r.borrow_mut() = b_weak.upgrade().unwrap().r.borrow_mut().take();
+    drop(b_weak);
+    let d = new();
+    *d.r.borrow_mut() = Some(a.downgrade());
+    loop {
+        if let GcProgress::Done(report) = gc.step(1) {
+            assert_eq!(report.traced, 5);
+            assert_eq!(report.dropped, 2);
+            break;
+        }
+    }
+    drop(gc);
+    assert!(c_weak.upgrade().is_some());
+}
+
+#[test]
+fn test_incremental_shared_rc() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        shared: std::rc::Rc<RefCell<Vec<GcObject<'c, Foo<'c>>>>>,
+        r: RefCell<Option<GcObject<'c, Foo<'c>>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.shared.trace(token);
+            self.r.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let shared = std::rc::Rc::new(RefCell::new(Vec::new()));
+    let new = || {
+        context.alloc(Foo {
+            shared: std::rc::Rc::new(RefCell::new(Vec::new())),
+            r: RefCell::new(None),
+        })
+    };
+    let a = context.alloc(Foo {
+        shared: shared.clone(),
+        r: RefCell::new(None),
+    });
+    let b = new();
+    let leaf = new();
+    *a.r.borrow_mut() = Some(b.downgrade());
+    *b.r.borrow_mut() = Some(leaf.downgrade());
+    let leaf_weak = context.downgrade_weak(&leaf);
+    drop((b, leaf));
+
+    let mut gc = context.start_incremental();
+    assert_eq!(gc.step(1), GcProgress::InProgress);
+    // Move the only edge to `leaf` into the `Rc` visited by the step that traced `a`.
+    let b = a.r.borrow().as_ref().unwrap().upgrade().unwrap();
+    shared.borrow_mut().push(b.r.borrow_mut().take().unwrap());
+    drop(b);
+    let report = loop {
+        if let GcProgress::Done(report) = gc.step(1) {
+            break report;
+        }
+    };
+    drop(gc);
+    assert_eq!(report.dropped, 0);
+    assert!(leaf_weak.is_live());
+    shared.borrow_mut().clear();
+}
+
+#[test]
+fn test_finalize_resurrect() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObject<'c, Self>>>,
+        graveyard: GcObject<'c, RefCell<Vec<GcObject<'c, Foo<'c>>>>>,
+        finalized: std::rc::Rc<Cell<usize>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+            self.graveyard.trace(token);
+        }
+
+        fn finalize(&self) {
+            self.finalized.set(self.finalized.get() + 1);
+            let graveyard = self.graveyard.upgrade().unwrap();
+            graveyard.borrow_mut().extend(self.r.borrow().clone());
+        }
+    }
+
+    let finalized = std::rc::Rc::new(Cell::new(0));
+    let context = GcContext::new();
+    let graveyard = context.alloc(RefCell::new(Vec::new()));
+    let x = context.alloc(Foo {
+        r: RefCell::new(None),
+        graveyard: graveyard.downgrade(),
+        finalized: finalized.clone(),
+    });
+    *x.r.borrow_mut() = Some(x.downgrade());
+    drop(x);
+
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!(report.dropped, 0);
+    assert_eq!(finalized.get(), 1);
+    assert_eq!(graveyard.borrow().len(), 1);
+
+    graveyard.borrow_mut().clear();
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!(report.dropped, 1);
+    assert_eq!(finalized.get(), 1);
+}
+
+#[test]
+fn test_import() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        value: usize,
+        r: RefCell<Option<GcObject<'c, Self>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    impl<'c, 'd> GcDeepClone<'c, 'd> for Foo<'c> {
+        type Target = Foo<'d>;
+
+        fn deep_clone(
+            this: &GcRoot<'c, Self>,
+            dst: &'d GcContext<'d>,
+            map: &mut GcCloneMap<'c, 'd>,
+        ) -> GcRoot<'d, Foo<'d>> {
+            let r = dst.alloc(Foo {
+                value: this.value,
+                r: RefCell::new(None),
+            });
+            map.insert(this, &r);
+            if let Some(child) = this.r.borrow().as_ref() {
+                *r.r.borrow_mut() = map.clone_object(child, dst).map(|x| x.downgrade());
+            }
+            r
+        }
+    }
+
+    let src = GcContext::new();
+    let a = src.alloc(Foo {
+        value: 1,
+        r: RefCell::new(None),
+    });
+    let b = src.alloc(Foo {
+        value: 2,
+        r: RefCell::new(Some(a.downgrade())),
+    });
+    *a.r.borrow_mut() = Some(b.downgrade());
+
+    let dst = GcContext::new();
+    let a2 = dst.import(&a.downgrade()).unwrap();
+    drop((a, b));
+    src.gc();
+    dst.gc();
+    let b2 = a2.r.borrow().as_ref().unwrap().upgrade().unwrap();
+    assert_eq!((a2.value, b2.value), (1, 2));
+    assert!(b2.r.borrow().as_ref().unwrap().upgrade().unwrap() == a2);
+
+    // The map roots its sources, so their addresses are not reused while it is alive.
+    let mut map = GcCloneMap::new();
+    let c = src.alloc(Foo {
+        value: 3,
+        r: RefCell::new(None),
+    });
+    let c_weak = src.downgrade_weak(&c);
+    let c2 = map.clone_object(&c.downgrade(), &dst).unwrap();
+    assert_eq!(map.get(&c).unwrap(), c2);
+    drop(c);
+    src.gc();
+    assert!(c_weak.is_live());
+    drop(map);
+    src.gc();
+    assert!(!c_weak.is_live());
+}
+
+#[test]
+fn test_inspect_heap() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(1u32);
+    let b = context.alloc(String::from("b"));
+    let mut names = Vec::new();
+    context.inspect_heap(|value| names.push(value.type_name()));
+    names.sort();
+    assert_eq!(names, ["alloc::string::String", "u32"]);
+    drop((a, b));
+    let mut count = 0;
+    context.inspect_heap(|_| count += 1);
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn test_min_heap_before_gc() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.set_auto_gc(2);
+    context.set_min_heap_before_gc(10);
+    let roots = (0..10).map(|i| context.alloc(i)).collect::<Vec<_>>();
+    assert_eq!(context.total_gc_duration(), Duration::ZERO);
+    drop(roots);
+    let _a = context.alloc(10);
+    let _b = context.alloc(11);
+    assert_ne!(context.total_gc_duration(), Duration::ZERO);
+
+    // Only the live set counts, not every allocation made so far.
+    let context = GcContext::new();
+    context.set_auto_gc(2);
+    context.set_min_heap_before_gc(10);
+    for i in 0..8 {
+        drop(context.alloc(i));
+    }
+    context.gc();
+    context.reset_stats();
+    let roots = (0..8).map(|i| context.alloc(i)).collect::<Vec<_>>();
+    assert_eq!(context.total_gc_duration(), Duration::ZERO);
+    drop(roots);
+}
+
+#[test]
+fn test_reset_stats() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.set_auto_gc(2);
+    let a = context.alloc(1);
+    context.gc();
+    assert_ne!(context.total_gc_duration(), Duration::ZERO);
+    assert_eq!(context.gc_count(), 1);
+    assert_eq!(context.total_allocs(), 1);
+    context.reset_stats();
+    assert_eq!(context.total_gc_duration(), Duration::ZERO);
+    assert_eq!(context.last_gc_duration(), Duration::ZERO);
+    assert_eq!(context.gc_count(), 0);
+    assert_eq!(context.total_allocs(), 0);
+    assert_eq!(context.stats().estimated_live_objects, 1);
+    assert_eq!(*a, 1);
+    // the auto gc schedule is kept, so this allocation collects
+    let _b = context.alloc(2);
+    assert_eq!(context.gc_count(), 1);
+    context.gc();
+    assert_ne!(context.total_gc_duration(), Duration::ZERO);
+    assert_eq!(context.gc_count(), 2);
+    assert_eq!(context.total_allocs(), 1);
+}
+
+#[test]
+fn test_alloc_batch() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.set_auto_gc(2);
+    let roots = context.alloc_batch(0..5);
+    assert_eq!(context.total_gc_duration(), Duration::ZERO);
+    assert_eq!(
+        roots.iter().map(|x| **x).collect::<Vec<_>>(),
+        [0, 1, 2, 3, 4]
+    );
+    let _a = context.alloc(5);
+    assert_ne!(context.total_gc_duration(), Duration::ZERO);
+    assert!(context.alloc_batch(Vec::<i32>::new()).is_empty());
+}
+
+#[test]
+fn test_leak() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(String::from("leaked")).leak();
+    context.gc();
+    assert_eq!(a, "leaked");
+    let mut count = 0;
+    context.inspect_heap(|_| count += 1);
+    assert_eq!(count, 1);
+}
+
+#[test]
+fn test_hash_map_in_cycle_drop() {
+    let _ = env_logger::try_init();
+
+    struct Node<'c> {
+        map: GcObject<'c, Map<'c>>,
+        upgraded: std::rc::Rc<Cell<usize>>,
+    }
+
+    impl<'c> Drop for Node<'c> {
+        fn drop(&mut self) {
+            if let Some(map) = self.map.upgrade() {
+                self.upgraded
+                    .set(self.upgraded.get() + map.map.borrow().len());
+            }
+        }
+    }
+
+    impl<'c> GcTarget<'c> for Node<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.map.trace(token);
+        }
+    }
+
+    struct Map<'c> {
+        map: RefCell<std::collections::HashMap<GcObject<'c, Node<'c>>, GcObject<'c, Node<'c>>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Map<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.map.trace(token);
+        }
+    }
+
+    let upgraded = std::rc::Rc::new(Cell::new(0));
+    let context = GcContext::new();
+    let map = context.alloc(Map {
+        map: RefCell::new(std::collections::HashMap::new()),
+    });
+    for _ in 0..100 {
+        let k = context.alloc(Node {
+            map: map.downgrade(),
+            upgraded: upgraded.clone(),
+        });
+        let v = context.alloc(Node {
+            map: map.downgrade(),
+            upgraded: upgraded.clone(),
+        });
+        map.map.borrow_mut().insert(k.downgrade(), v.downgrade());
+    }
+    drop(map);
+    context.gc();
+    assert_eq!(upgraded.get(), 0);
+    let mut count = 0;
+    context.inspect_heap(|_| count += 1);
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn test_swap() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let mut a = context.alloc(String::from("old"));
+    assert_eq!(context.swap(&mut a, String::from("new")).unwrap(), "old");
+    assert_eq!(*a, "new");
+    let b = a.clone();
+    assert_eq!(
+        context.swap(&mut a, String::from("other")).unwrap_err(),
+        "other"
+    );
+    drop(b);
+    context.gc();
+    assert_eq!(*a, "new");
+}
+
+#[test]
+fn test_freeze_current() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObject<'c, Self>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let a = context.alloc(Foo {
+        r: RefCell::new(None),
+    });
+    let b = context.alloc(Foo {
+        r: RefCell::new(Some(a.downgrade())),
+    });
+    *a.r.borrow_mut() = Some(b.downgrade());
+    drop(b);
+    context.freeze_current();
+
+    let c = context.alloc(Foo {
+        r: RefCell::new(Some(a.downgrade())),
+    });
+    *a.r.borrow_mut() = Some(c.downgrade());
+    drop(c);
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!((report.traced, report.dropped), (1, 0));
+    assert!(a.r.borrow().as_ref().unwrap().upgrade().is_some());
+
+    drop(a);
+    context.collect_frozen();
+    let mut count = 0;
+    context.inspect_heap(|_| count += 1);
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn test_gc_conservative() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(1).downgrade();
+    let slots = [0, a.base_ptr().as_ptr() as usize, 3];
+    let range = slots.as_ptr_range();
+    let report = unsafe { context.gc_conservative(range.end.cast(), range.start.cast()) }.unwrap();
+    assert_eq!((report.traced, report.held), (1, 1));
+    assert_eq!(*a.upgrade().unwrap(), 1);
+    drop(a);
+    let mut count = 0;
+    context.inspect_heap(|_| count += 1);
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn test_counts() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(1);
+    let b = a.downgrade();
+    let c = b.clone().cast_thin();
+    assert_eq!((b.root_count(), b.weak_count()), (1, 2));
+    drop(a);
+    assert_eq!((c.root_count(), c.weak_count()), (0, 2));
+}
+
+#[test]
+fn test_option_array_slots() {
+    let _ = env_logger::try_init();
+
+    struct Table<'c> {
+        slots: RefCell<[Option<GcObject<'c, Table<'c>>>; 8]>,
+        value: usize,
+    }
+
+    impl<'c> GcTarget<'c> for Table<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.slots.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let table = context.alloc(Table {
+        slots: RefCell::new(Default::default()),
+        value: 0,
+    });
+    for i in [1, 4, 6] {
+        let value = context.alloc(Table {
+            slots: RefCell::new(Default::default()),
+            value: i,
+        });
+        value.slots.borrow_mut()[0] = Some(table.downgrade());
+        table.slots.borrow_mut()[i] = Some(value.downgrade());
+    }
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!((report.held, report.dropped), (4, 0));
+    let slots = table.slots.borrow();
+    for (i, slot) in slots.iter().enumerate() {
+        match slot {
+            Some(x) => assert_eq!(x.upgrade().unwrap().value, i),
+            None => assert!(![1, 4, 6].contains(&i)),
+        }
+    }
+}
+
+#[test]
+fn test_drop_order() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObject<'c, Self>>>,
+        value: usize,
+        order: std::rc::Rc<RefCell<Vec<usize>>>,
+    }
+
+    impl<'c> Drop for Foo<'c> {
+        fn drop(&mut self) {
+            self.order.borrow_mut().push(self.value);
+        }
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    fn run(drop_order: DropOrder) -> Vec<usize> {
+        let order = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let context = GcContext::new();
+        context.set_drop_order(drop_order);
+        let roots = (0..3)
+            .map(|value| {
+                context.alloc(Foo {
+                    r: RefCell::new(None),
+                    value,
+                    order: order.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        for (i, root) in roots.iter().enumerate() {
+            *root.r.borrow_mut() = Some(roots[(i + 1) % 3].downgrade());
+        }
+        drop(roots);
+        context.gc();
+        let order = order.borrow().clone();
+        order
+    }
+
+    assert_eq!(run(DropOrder::NewestFirst), [2, 1, 0]);
+    assert_eq!(run(DropOrder::OldestFirst), [0, 1, 2]);
+}
+
+#[test]
+fn test_try_from_dyn() {
+    let _ = env_logger::try_init();
+
+    struct Foo(usize);
+
+    impl<'c> GcTarget<'c> for Foo {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            let _ = token;
+        }
+
+        fn as_any(&self) -> Option<&dyn Any> {
+            Some(self)
+        }
+    }
+
+    let context = GcContext::new();
+    let a = context.alloc(Foo(1)).cast_dyn();
+    let a = GcRoot::<String>::try_from(a).unwrap_err();
+    let a: GcRoot<Foo> = a.try_into().unwrap();
+    assert_eq!(a.0, 1);
+}
+
+#[test]
+fn test_snapshot_diff() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let _a = context.alloc(1u32);
+    let before = context.snapshot();
+    let b = context.alloc(String::from("b"));
+    let c = context.alloc(2u32);
+    let d = context.alloc(3u32);
+    let after = context.snapshot();
+    assert_eq!(after.len(), 4);
+    let diff = before.diff(&after);
+    assert_eq!(diff.len(), 3);
+    assert_eq!(
+        diff.counts().into_iter().collect::<Vec<_>>(),
+        [("alloc::string::String", 1), ("u32", 2)]
+    );
+    drop((b, c, d));
+    assert!(before.diff(&context.snapshot()).is_empty());
+}
+
+#[test]
+fn test_thin_cycle() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObjectThin<'c>>>,
+        drops: std::rc::Rc<Cell<usize>>,
+    }
+
+    impl<'c> Drop for Foo<'c> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    let drops = std::rc::Rc::new(Cell::new(0));
+    let context = GcContext::new();
+    let x = context.alloc(Foo {
+        r: RefCell::new(None),
+        drops: drops.clone(),
+    });
+    let y = context.alloc(Foo {
+        r: RefCell::new(Some(x.downgrade().cast_thin())),
+        drops: drops.clone(),
+    });
+    *x.r.borrow_mut() = Some(y.downgrade().cast_thin());
+    drop(y);
+    context.gc();
+    assert_eq!(drops.get(), 0);
+    drop(x);
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!((report.held, report.dropped), (0, 2));
+    assert_eq!(drops.get(), 2);
+}
+
+#[test]
+#[should_panic(expected = "trace recursion too deep")]
+fn test_trace_depth_limit() {
+    let _ = env_logger::try_init();
+
+    struct Node {
+        next: RefCell<Option<std::rc::Rc<Node>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Node {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    context.set_trace_depth_limit(100);
+    let mut node = std::rc::Rc::new(Node {
+        next: RefCell::new(None),
+    });
+    for _ in 0..200 {
+        node = std::rc::Rc::new(Node {
+            next: RefCell::new(Some(node)),
+        });
+    }
+    let _root = context.alloc(node);
+    context.gc();
+}
+
+#[test]
+fn test_trace_shared_rc() {
+    let _ = env_logger::try_init();
+
+    struct Node {
+        children: Vec<std::rc::Rc<Node>>,
+        visits: Rc<Cell<usize>>,
+    }
+
+    impl<'c> GcTarget<'c> for Node {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.visits.set(self.visits.get() + 1);
+            self.children.trace(token);
+        }
+    }
+
+    // Each level holds the level below twice, 2^40 paths without deduplication.
+    let visits = Rc::new(Cell::new(0));
+    let mut node = std::rc::Rc::new(Node {
+        children: Vec::new(),
+        visits: visits.clone(),
+    });
+    for _ in 0..40 {
+        node = std::rc::Rc::new(Node {
+            children: vec![node.clone(), node],
+            visits: visits.clone(),
+        });
+    }
+    let context = GcContext::new();
+    let _a = context.alloc(node.clone());
+    let _b = context.alloc(node);
+    context.gc();
+    assert_eq!(visits.get(), 41);
+
+    // A cycle of `Rc` is traced once instead of forever.
+    struct Cycle {
+        next: RefCell<Option<std::rc::Rc<Cycle>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Cycle {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+
+    let cycle = std::rc::Rc::new(Cycle {
+        next: RefCell::new(None),
+    });
+    *cycle.next.borrow_mut() = Some(cycle.clone());
+    let root = context.alloc(cycle.clone());
+    context.gc();
+    cycle.next.take();
+    drop(root);
+}
+
+#[test]
+#[should_panic(expected = "trace recursion too deep")]
+fn test_deep_box_list() {
+    let _ = env_logger::try_init();
+
+    struct Node {
+        next: Option<Box<Node>>,
+    }
+
+    impl<'c> GcTarget<'c> for Node {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    context.set_trace_depth_limit(1000);
+    let mut list = Node { next: None };
+    for _ in 0..2000 {
+        list = Node {
+            next: Some(Box::new(list)),
+        };
+    }
+    let _root = context.alloc(list);
+    context.gc();
+}
+
+#[test]
+fn test_deep_object_list() {
+    let _ = env_logger::try_init();
+
+    struct Node<'c> {
+        next: RefCell<Option<GcObject<'c, Node<'c>>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Node<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let head = context.alloc(Node {
+        next: RefCell::new(None),
+    });
+    let mut tail = head.downgrade();
+    for _ in 0..100_000 {
+        tail = context
+            .alloc(Node {
+                next: RefCell::new(Some(tail)),
+            })
+            .downgrade();
+    }
+    // Close the cycle, dropping the root then leaves the whole list to the sweep instead of
+    // freeing it recursively.
+    *head.next.borrow_mut() = Some(tail);
+    context.gc();
+    assert_eq!(context.stats().estimated_live_objects, 100_001);
+    drop(head);
+    context.gc();
+    assert!(context.snapshot().is_empty());
+}
+
+#[test]
+fn test_alloc_mut() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: GcMut<'c, Option<GcObject<'c, Self>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let a = context.alloc(Foo {
+        r: GcMut::new(None),
+    });
+    let b = context.alloc(Foo {
+        r: GcMut::new(Some(a.downgrade())),
+    });
+    a.r.set(Some(b.downgrade()));
+    drop(b);
+    context.gc();
+    assert!(a.r.with(|r| r.as_ref().unwrap().upgrade().is_some()));
+
+    let counter = context.alloc_mut(1);
+    counter.set(counter.get() + 1);
+    assert_eq!(counter.replace(5), 2);
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        counter.with(|_| counter.set(0))
+    }))
+    .is_err());
+    assert_eq!(counter.get(), 5);
+}
+
+#[test]
+fn test_try_alloc() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.try_alloc(String::from("a")).unwrap();
+    assert_eq!(*a, "a");
+    drop(a);
+    context.gc();
+    assert_eq!(GcAllocError.to_string(), "memory allocation failed");
+}
+
+#[test]
+fn test_type_finalizer() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObject<'c, Self>>>,
+        name: GcObject<'c, String>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+            self.name.trace(token);
+        }
+    }
+
+    let finalized = std::rc::Rc::new(RefCell::new(Vec::new()));
+    let context = GcContext::new();
+    let f = finalized.clone();
+    context.set_type_finalizer::<String>(Box::new(move |x| f.borrow_mut().push(x.clone())));
+    let a = context.alloc(Foo {
+        r: RefCell::new(None),
+        name: context.alloc(String::from("cycle")).downgrade(),
+    });
+    *a.r.borrow_mut() = Some(a.downgrade());
+    drop(a);
+    drop(context.alloc(String::from("rc")));
+    let _b = context.alloc(String::from("rooted"));
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!(report.dropped, 2);
+    assert_eq!(*finalized.borrow(), ["cycle"]);
+
+    // without as_any the finalizer can't recognize the type
+    struct Plain<'c>(RefCell<Option<GcObject<'c, Self>>>);
+    impl<'c> GcTarget<'c> for Plain<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.0.trace(token);
+        }
+    }
+    let plain = context.alloc(Plain(RefCell::new(None)));
+    *plain.0.borrow_mut() = Some(plain.downgrade());
+    let f = finalized.clone();
+    context.set_type_finalizer::<Plain<'static>>(Box::new(move |_| {
+        f.borrow_mut().push(String::from("plain"))
+    }));
+    drop(plain);
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!(report.dropped, 1);
+    assert_eq!(*finalized.borrow(), ["cycle"]);
+}
+
+#[test]
+fn test_get_mut() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let mut a = context.alloc(vec![1]);
+    a.get_mut().unwrap().push(2);
+    let b = a.downgrade();
+    assert!(a.get_mut().is_none());
+    drop(b);
+    let c = a.clone();
+    assert!(a.get_mut().is_none());
+    drop(c);
+    assert_eq!(*a.get_mut().unwrap(), [1, 2]);
+}
+
+#[test]
+fn test_clear() {
+    let _ = env_logger::try_init();
+    let drops = std::rc::Rc::new(Cell::new(0));
+
+    struct Foo(std::rc::Rc<Cell<usize>>);
+
+    impl Drop for Foo {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    impl<'c> GcTarget<'c> for Foo {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            let _ = token;
+        }
+    }
+
+    let context = GcContext::new();
+    context.set_auto_gc(100);
+    let _ = context.alloc(Foo(drops.clone())).leak();
+    forget(context.alloc(Foo(drops.clone())));
+    unsafe { context.clear() };
+    assert_eq!(drops.get(), 2);
+    assert_eq!(context.estimated_heap_bytes(), 0);
+    let _a = context.alloc(Foo(drops.clone()));
+}
+
+#[test]
+fn test_force_collect_until_stable() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObject<'c, Self>>>,
+        hold: Option<GcRoot<'c, Foo<'c>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+            self.hold.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let inner = context.alloc(Foo {
+        r: RefCell::new(None),
+        hold: None,
+    });
+    *inner.r.borrow_mut() = Some(inner.downgrade());
+    let outer = context.alloc(Foo {
+        r: RefCell::new(None),
+        hold: Some(inner),
+    });
+    *outer.r.borrow_mut() = Some(outer.downgrade());
+    drop(outer);
+    let report = context.force_collect_until_stable();
+    assert_eq!((report.held, report.dropped), (0, 2));
+    assert_eq!(context.collect_on_pressure(), GcCollectReport::default());
+}
+
+#[test]
+fn test_arc_slice() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(1);
+    let b = context.alloc(2);
+    let nodes: std::sync::Arc<[GcObject<i32>]> = vec![a.downgrade(), b.downgrade()].into();
+    let holder = context.alloc(nodes.clone());
+    drop((a, b));
+    let shared = holder.clone();
+    let report = context.collect_to_completion_with_finalizers();
+    assert_eq!((report.held, report.dropped), (3, 0));
+    let values = shared
+        .iter()
+        .map(|x| *x.upgrade().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(values, [1, 2]);
+    drop((holder, shared));
+    drop(nodes);
+}
+
+#[test]
+fn test_incremental_step_for() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let roots = (0..1000).map(|i| context.alloc(i)).collect::<Vec<_>>();
+    let mut gc = context.start_incremental();
+    assert_eq!(gc.step_for(Duration::ZERO), GcProgress::InProgress);
+    match gc.step_for(Duration::from_secs(10)) {
+        GcProgress::Done(report) => assert_eq!((report.held, report.dropped), (1000, 0)),
+        GcProgress::InProgress => panic!("incremental gc not done"),
+    }
+    drop(roots);
+}
+
+#[test]
+fn test_thin_from_raw() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(String::from("a"));
+    let raw = a.base_ptr().as_ptr() as *const ();
+    assert!(unsafe { GcObjectThin::from_raw(std::ptr::null()) }.is_none());
+    let b = unsafe { GcObjectThin::from_raw(raw) }.unwrap();
+    assert_eq!(b.weak_count(), 1);
+    let b = b.upgrade().unwrap();
+    assert_eq!(
+        (*b).as_any().unwrap().downcast_ref::<String>().unwrap(),
+        "a"
+    );
+}
+
+#[test]
+fn test_try_incremental_during() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        r: RefCell<Option<GcObject<'c, Self>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.r.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let garbage = context.alloc(Foo {
+        r: RefCell::new(None),
+    });
+    *garbage.r.borrow_mut() = Some(garbage.downgrade());
+    drop(garbage);
+    let _roots = (0..1000).map(|i| context.alloc(i)).collect::<Vec<_>>();
+
+    assert!(!context.try_incremental_during(Instant::now()));
+    assert!(context.try_incremental_during(Instant::now() + Duration::from_secs(10)));
+    let mut count = 0;
+    context.inspect_heap(|_| count += 1);
+    assert_eq!(count, 1000);
+}
+
+#[test]
+fn test_gc_trigger() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let seen = std::rc::Rc::new(Cell::new(0));
+    let s = seen.clone();
+    context.set_gc_trigger(Box::new(move |stats| {
+        s.set(stats.allocs_since_gc);
+        stats.allocs_since_gc >= 3
+    }));
+    let roots = (0..3).map(|i| context.alloc(i)).collect::<Vec<_>>();
+    assert_eq!(seen.get(), 3);
+    assert_eq!(context.stats().allocs_since_gc, 0);
+    assert_eq!(context.stats().estimated_live_objects, 3);
+    context.clear_gc_trigger();
+    drop(roots);
+}
+
+#[test]
+fn test_gc_trigger_paused() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.set_gc_trigger(Box::new(|_| true));
+    let pause = context.pause_gc();
+    let _a = context.alloc(1);
+    let _b = context.alloc(2);
+    assert_eq!(context.stats().allocs_since_gc, 2);
+    drop(pause);
+    let _c = context.alloc(3);
+    assert_eq!(context.stats().allocs_since_gc, 0);
+}
+
+#[test]
+fn test_pause_gc_nested() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.set_auto_gc(1);
+    let outer = context.pause_gc();
+    let inner = context.pause_gc();
+    let _a = context.alloc(1);
+    drop(outer);
+    let _b = context.alloc(2);
+    assert_eq!(context.stats().allocs_since_gc, 2);
+    context.set_auto_gc(3);
+    drop(inner);
+    assert_eq!(context.inner().auto_gc.get(), 3);
+    let _c = context.alloc(3);
+    assert_eq!(context.stats().allocs_since_gc, 3);
+    let _d = context.alloc(4);
+    assert_eq!(context.stats().allocs_since_gc, 4);
+    let _e = context.alloc(5);
+    assert_eq!(context.stats().allocs_since_gc, 1);
+
+    let pause = context.pause_gc();
+    let copy = context.clone_config();
+    drop(pause);
+    assert_eq!(copy.inner().auto_gc.get(), 3);
+}
+
+#[test]
+fn test_root_raw() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let raw = context.alloc(String::from("raw")).into_raw();
+    context.gc();
+    let a = unsafe { GcRoot::from_raw(raw) };
+    assert_eq!(*a, "raw");
+    assert_eq!(a.downgrade().root_count(), 1);
+    drop(a);
+    let mut count = 0;
+    context.inspect_heap(|_| count += 1);
+    assert_eq!(count, 0);
+}
+
+#[test]
+#[should_panic(expected = "leak 1 target: [\"alloc::string::String\"]")]
+fn test_panic_on_leak() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.panic_on_leak(true);
+    let _ = context.alloc(String::from("leak")).leak();
+}
+
+#[test]
+fn test_gc_any() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let values: Vec<Box<dyn GcAny>> = vec![Box::new(String::from("a")), Box::new(1u32)];
+    let a = context.alloc(values);
+    context.gc();
+    assert!(a[0].is::<String>());
+    assert_eq!(a[0].downcast_ref::<String>().unwrap(), "a");
+    assert_eq!(a[1].downcast_ref::<u32>(), Some(&1));
+    assert!(a[1].downcast_ref::<String>().is_none());
+    let mut b: Box<dyn GcAny> = Box::new(2u32);
+    *b.downcast_mut::<u32>().unwrap() += 1;
+    assert_eq!(b.downcast_ref::<u32>(), Some(&3));
+
+    struct Foo<'c>(GcObject<'c, String>);
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.0.trace(token);
+        }
+    }
+    gc_any!(Foo<'c> => Foo<'static>);
+
+    let root = context.alloc(String::from("name"));
+    let name = context.downgrade_weak(&root);
+    let foo: Box<dyn GcAny> = Box::new(Foo(root.downgrade()));
+    let foo = context.alloc(foo);
+    drop(root);
+    context.gc();
+    assert!(!foo.is::<String>());
+    assert_eq!(
+        *foo.downcast_ref::<Foo>().unwrap().0.upgrade().unwrap(),
+        "name"
+    );
+    assert!(name.is_live());
+}
+
+#[test]
+fn test_alloc_str() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc_str("hello");
+    let b = context.alloc_slice_copy(&[1u8, 2, 3]);
+    context.gc();
+    assert_eq!(&**a, "hello");
+    assert_eq!(&**b, &[1, 2, 3]);
+}
+
+#[test]
+fn test_is_marked() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        child: Option<GcObject<'c, Self>>,
+        child_marked: Cell<Option<bool>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            if let Some(child) = &self.child {
+                self.child_marked.set(Some(token.is_marked(child)));
+                token.accept(child);
+            }
+        }
+    }
+    let context = GcContext::new();
+    let foo = |child| Foo {
+        child,
+        child_marked: Cell::new(None),
+    };
+    let rooted = context.alloc(foo(None));
+    let unrooted = context.alloc(foo(None)).downgrade();
+    let a = context.alloc(foo(Some(rooted.downgrade())));
+    let b = context.alloc(foo(Some(unrooted)));
+    context.gc();
+    assert_eq!(a.child_marked.get(), Some(true));
+    assert_eq!(b.child_marked.get(), Some(false));
+}
+
+#[cfg(feature = "debug-checks")]
+#[test]
+#[should_panic(expected = "object used with a context that did not allocate it")]
+fn test_debug_checks_owner() {
+    let _ = env_logger::try_init();
+    let a = GcContext::new();
+    let b = GcContext::new();
+    let mut obj = a.alloc(1);
+    let _ = b.swap(&mut obj, 2);
+}
+
+#[test]
+fn test_weak() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(String::from("a"));
+    let weak = context.downgrade_weak(&a);
+    context.gc();
+    assert!(weak.is_live());
+    assert_eq!(&*weak.upgrade().unwrap(), "a");
+    drop(a);
+    context.gc();
+    assert!(!weak.is_live());
+    assert!(weak.upgrade().is_none());
+    assert!(context.snapshot().is_empty());
+
+    let b = context.alloc(1);
+    let weak = context.downgrade_weak(&b);
+    drop(weak.clone());
+    drop(b);
+    drop(weak);
+    assert!(context.snapshot().is_empty());
+}
+
+#[test]
+fn test_weak_key_map() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        map: RefCell<weak_map::WeakKeyMap<'c, String, GcObject<'c, String>>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.map.trace(token);
+        }
+    }
+    let context = GcContext::new();
+    let foo = context.alloc(Foo {
+        map: RefCell::new(weak_map::WeakKeyMap::new()),
+    });
+    let a = context.alloc(String::from("a"));
+    let b = context.alloc(String::from("b"));
+    let value = context.alloc(String::from("value"));
+    let mut map = foo.map.borrow_mut();
+    map.insert(context.downgrade_weak(&a), value.downgrade());
+    map.insert(context.downgrade_weak(&b), a.downgrade());
+    drop(map);
+    drop(value);
+    context.gc();
+    assert_eq!(
+        &**foo.map.borrow().get(&a).unwrap().upgrade().unwrap(),
+        "value"
+    );
+    assert_eq!(foo.map.borrow().len(), 2);
+
+    drop(b);
+    context.gc();
+    assert_eq!(foo.map.borrow().len(), 1);
+    foo.map.borrow_mut().prune();
+    drop(a);
+    context.gc();
+    assert!(foo.map.borrow().is_empty());
+    foo.map.borrow_mut().prune();
+    context.gc();
+    assert_eq!(context.snapshot().len(), 1);
+}
+
+#[cfg(feature = "pooling")]
+#[test]
+fn test_weak_key_map_reused_address() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let mut map = weak_map::WeakKeyMap::new();
+    let a = context.alloc(String::from("a"));
+    assert!(map.insert(context.downgrade_weak(&a), 1).is_none());
+    let address = a.base_ptr();
+    drop(a);
+    context.gc();
+    let b = context.alloc(String::from("b"));
+    assert_eq!(b.base_ptr(), address);
+    assert!(map.get(&b).is_none());
+    assert!(map.insert(context.downgrade_weak(&b), 2).is_none());
+    assert_eq!(map.insert(context.downgrade_weak(&b), 3), Some(2));
+}
+
+#[test]
+fn test_weak_key_map_prune_after_gc() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let map = Rc::new(RefCell::new(weak_map::WeakKeyMap::new()));
+    weak_map::WeakKeyMap::prune_after_gc(&map, &context);
+    let value = Rc::new(());
+    let a = context.alloc(1);
+    map.borrow_mut()
+        .insert(context.downgrade_weak(&a), value.clone());
+    context.gc();
+    assert_eq!(Rc::strong_count(&value), 2);
+
+    drop(a);
+    let borrow = map.borrow();
+    context.gc();
+    drop(borrow);
+    assert_eq!(Rc::strong_count(&value), 2);
+    context.gc();
+    assert_eq!(Rc::strong_count(&value), 1);
+
+    drop(map);
+    context.gc();
+    assert!(context.inner().post_gc_hooks.borrow().is_empty());
+}
+
+#[test]
+fn test_ephemeron() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        other: RefCell<Option<GcObject<'c, Self>>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.other.trace(token);
+        }
+    }
+    let context = GcContext::new();
+    let foo = || Foo {
+        other: RefCell::new(None),
+    };
+    let a = context.alloc(foo());
+    let b = context.alloc(foo());
+    let c = context.alloc(foo());
+    *c.other.borrow_mut() = Some(a.downgrade());
+    let ab = context.ephemeron(&a, &b);
+    let bc = context.ephemeron(&b, &c);
+    drop(b);
+    drop(c);
+    context.gc();
+    assert_eq!(context.snapshot().len(), 3);
+    assert!(ab.value().is_some());
+    assert!(bc.value().is_some());
+
+    drop(a);
+    context.gc();
+    assert!(context.snapshot().is_empty());
+    assert!(!ab.is_live());
+    assert!(ab.key().is_none());
+    assert!(bc.value().is_none());
+
+    let d = context.alloc(foo());
+    let e = context.alloc(foo());
+    let de = context.ephemeron(&d, &e);
+    drop(e);
+    drop(de);
+    context.gc();
+    assert_eq!(context.snapshot().len(), 1);
+}
+
+#[cfg(feature = "alloc-tracking")]
+#[test]
+fn test_alloc_tracking() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let mut roots = Vec::new();
+    for i in 0..3 {
+        roots.push(context.alloc(i));
+    }
+    let a = context.alloc(3);
+    let here = Location::caller();
+    let b = context.alloc_tracked(4, here);
+    let snapshot = context.snapshot();
+    let sites = snapshot.sites();
+    assert_eq!(sites.len(), 3);
+    assert!(sites.values().any(|&count| count == 3));
+    let location = snapshot.location(a.ptr.as_ptr() as usize).unwrap();
+    assert_eq!(location.file(), file!());
+    assert_eq!(snapshot.location(b.ptr.as_ptr() as usize), Some(here));
+}
+
+#[test]
+fn test_upgrade_map() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(String::from("abc"));
+    let obj = a.downgrade();
+    let weak = context.downgrade_weak(&a);
+    assert_eq!(obj.upgrade_map(|s| s.len()), Some(3));
+    assert_eq!(obj.root_count(), 1);
+    drop(obj);
+    drop(a);
+    context.gc();
+    assert_eq!(weak.upgrade_map(|s| s.len()), None);
+}
+
+#[cfg(feature = "pooling")]
+#[test]
+fn test_pooling() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        this: RefCell<Option<GcObject<'c, Self>>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.this.trace(token);
+        }
+    }
+    let context = GcContext::new();
+    let a = context.alloc(Foo {
+        this: RefCell::new(None),
+    });
+    *a.this.borrow_mut() = Some(a.downgrade());
+    let address = a.ptr.as_ptr() as usize;
+    drop(a);
+    context.gc();
+    let b = context.alloc(Foo {
+        this: RefCell::new(None),
+    });
+    assert_eq!(b.ptr.as_ptr() as usize, address);
+    drop(b);
+
+    // A box released by reference counting is pooled too.
+    let c = context.alloc(Foo {
+        this: RefCell::new(None),
+    });
+    assert_eq!(c.ptr.as_ptr() as usize, address);
+    drop(c);
+    let pooled = || {
+        let free_blocks = context.inner().free_blocks.borrow();
+        free_blocks
+            .iter()
+            .map(|(_, blocks)| blocks.len())
+            .sum::<usize>()
+    };
+    assert_eq!(pooled(), 1);
+    let d = context.alloc(Foo {
+        this: RefCell::new(None),
+    });
+    assert_eq!(d.ptr.as_ptr() as usize, address);
+    assert_eq!(pooled(), 0);
+    drop(d);
+    context.compact_free_list();
+    assert_eq!(pooled(), 0);
+}
+
+#[test]
+fn test_upgrade_all() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(1);
+    let b = context.alloc(2);
+    let handles = vec![a.downgrade(), b.downgrade()];
+    assert_eq!(
+        GcObject::upgrade_all(&handles)
+            .iter()
+            .map(|root| **root)
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+}
+
+#[test]
+fn test_trace_profile() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.gc();
+    assert!(context.last_trace_profile().is_empty());
+    context.set_trace_profiling(true);
+    let _a = context.alloc(1u32);
+    let _b = context.alloc(String::from("b"));
+    let _c = context.alloc(String::from("c"));
+    context.gc();
+    assert_eq!(
+        context.last_trace_profile(),
+        [("alloc::string::String", 2), ("u32", 1)]
+    );
+}
+
+#[test]
+fn test_weak_value_map() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let mut map = weak_map::WeakValueMap::new();
+    let a = context.alloc(String::from("a"));
+    let b = context.alloc(String::from("b"));
+    assert!(map.insert("a", context.downgrade_weak(&a)).is_none());
+    map.insert("b", context.downgrade_weak(&b));
+    context.gc();
+    assert_eq!(&*map.get("a").unwrap(), "a");
+    assert_eq!(map.len(), 2);
+
+    drop(a);
+    context.gc();
+    assert!(map.get("a").is_none());
+    assert!(!map.contains_key("a"));
+    assert_eq!(map.len(), 1);
+    map.prune();
+    assert_eq!(&*map.remove("b").unwrap(), "b");
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_weak_value_map_prune_after_gc() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let map = Rc::new(RefCell::new(weak_map::WeakValueMap::new()));
+    weak_map::WeakValueMap::prune_after_gc(&map, &context);
+    let key = Rc::new(1);
+    let a = context.alloc(String::from("a"));
+    map.borrow_mut()
+        .insert(key.clone(), context.downgrade_weak(&a));
+    context.gc();
+    assert_eq!(Rc::strong_count(&key), 2);
+
+    drop(a);
+    context.gc();
+    assert_eq!(Rc::strong_count(&key), 1);
+    assert!(map.borrow().is_empty());
+}
+
+#[test]
+fn test_prune_dead() {
+    use weak_map::PruneDead;
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(String::from("a"));
+    let b = context.alloc(String::from("b"));
+    let observers = context.alloc(RefCell::new(vec![
+        context.downgrade_weak(&a),
+        context.downgrade_weak(&b),
+    ]));
+    drop(a);
+    context.gc();
+    assert_eq!(observers.borrow().len(), 2);
+    observers.borrow_mut().prune_dead();
+    assert_eq!(observers.borrow().len(), 1);
+    assert_eq!(&*observers.borrow()[0].upgrade().unwrap(), "b");
+
+    let mut queue = std::collections::VecDeque::from([context.downgrade_weak(&b)]);
+    drop(b);
+    context.gc();
+    queue.prune_dead();
+    assert!(queue.is_empty());
+    observers.borrow_mut().prune_dead();
+    assert!(observers.borrow().is_empty());
+}
+
+#[test]
+fn test_retention_path() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        next: RefCell<Option<GcObject<'c, Self>>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+    let context = GcContext::new();
+    let foo = || Foo {
+        next: RefCell::new(None),
+    };
+    let a = context.alloc(foo());
+    let b = context.alloc(foo());
+    let c = context.alloc(foo());
+    let d = context.alloc(foo());
+    *a.next.borrow_mut() = Some(b.downgrade());
+    *b.next.borrow_mut() = Some(c.downgrade());
+    *d.next.borrow_mut() = Some(d.downgrade());
+    let path = vec![
+        a.ptr.as_ptr() as usize,
+        b.ptr.as_ptr() as usize,
+        c.ptr.as_ptr() as usize,
+    ];
+    let target = c.downgrade().cast_thin();
+    drop(b);
+    drop(c);
+    assert_eq!(context.retention_path(&target), Some(path.clone()));
+    assert_eq!(
+        context.retention_path(&a.downgrade().cast_thin()),
+        Some(vec![path[0]])
+    );
+    let d_thin = d.downgrade().cast_thin();
+    drop(d);
+    assert_eq!(context.retention_path(&d_thin), None);
+    drop(d_thin);
+    assert_eq!(context.snapshot().len(), 4);
+    drop(target);
+    context.gc();
+    assert_eq!(context.snapshot().len(), 3);
+}
+
+#[test]
+fn test_sweep_step() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        next: RefCell<Option<GcObject<'c, Self>>>,
+        drops: Rc<Cell<usize>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+    impl<'c> Drop for Foo<'c> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+    let context = GcContext::new();
+    let drops = Rc::new(Cell::new(0));
+    let foo = || Foo {
+        next: RefCell::new(None),
+        drops: drops.clone(),
+    };
+    let a = context.alloc(foo());
+    let b = context.alloc(foo());
+    let c = context.alloc(foo());
+    *a.next.borrow_mut() = Some(b.downgrade());
+    *b.next.borrow_mut() = Some(c.downgrade());
+    *c.next.borrow_mut() = Some(a.downgrade());
+    let d = context.alloc(foo());
+    drop(a);
+    drop(b);
+    drop(c);
+    assert_eq!(context.mark_only(), 3);
+    assert_eq!(context.pending_sweep(), 3);
+    assert_eq!(context.snapshot().len(), 1);
+    assert_eq!(drops.get(), 0);
+    assert_eq!(context.sweep_step(2), 2);
+    assert_eq!(drops.get(), 2);
+    assert_eq!(context.sweep_step(2), 1);
+    assert_eq!(context.pending_sweep(), 0);
+    assert_eq!(context.sweep_step(2), 0);
+
+    let e = context.alloc(foo());
+    *e.next.borrow_mut() = Some(e.downgrade());
+    drop(e);
+    assert_eq!(context.mark_only(), 1);
+    drop(d);
+    context.gc();
+    assert_eq!(drops.get(), 5);
+    assert_eq!(context.pending_sweep(), 0);
+}
+
+#[test]
+#[allow(clippy::arc_with_non_send_sync)]
+fn test_mutex_locked_during_auto_gc() {
+    let _ = env_logger::try_init();
+    use std::sync::{Arc, Mutex};
+
+    struct Queue<'c> {
+        tasks: Arc<Mutex<Vec<GcObject<'c, u32>>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Queue<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.tasks.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let queue = context.alloc(Queue {
+        tasks: Arc::new(Mutex::new(Vec::new())),
+    });
+    let mut tasks = queue.tasks.lock().unwrap();
+    tasks.push(context.alloc(1).downgrade());
+    context.set_auto_gc(1);
+    let _other = context.alloc(2);
+    assert_eq!(context.stats().allocs_since_gc, 1);
+    assert_eq!(context.snapshot().len(), 3);
+    assert_eq!(*tasks[0].upgrade().unwrap(), 1);
+    drop(tasks);
+    context.gc();
+    assert_eq!(*queue.tasks.lock().unwrap()[0].upgrade().unwrap(), 1);
+}
+
+#[test]
+fn test_alloc_and_downgrade() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let list = context.alloc(RefCell::new(Vec::new()));
+    let (root, obj) = context.alloc_and_downgrade(1);
+    list.borrow_mut().push(obj);
+    drop(root);
+    context.gc();
+    assert_eq!(*list.borrow()[0].upgrade().unwrap(), 1);
+}
+
+#[test]
+fn test_alloc_cyclic_typed() {
+    struct Node<'c> {
+        value: i32,
+        this: GcObject<'c, Node<'c>>,
+    }
+
+    impl<'c> GcTarget<'c> for Node<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            token.accept(&self.this);
+        }
+    }
+
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let node = context.alloc_cyclic_typed(|this| {
+        assert!(this.upgrade().is_none());
+        Node {
+            value: 1,
+            this: this.clone(),
+        }
+    });
+    context.gc();
+    assert_eq!(node.this.upgrade().unwrap().value, 1);
+    assert_eq!(node.this.base_ptr(), node.base_ptr());
+    drop(node);
+    context.gc();
+    assert_eq!(context.estimated_heap_bytes(), 0);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_fault_injector() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let fault = Rc::new(Cell::new(None));
+    let injected = fault.clone();
+    context.set_fault_injector(Box::new(move |point| injected.get() == Some(point)));
+    let a = context.alloc(RefCell::new(Vec::new()));
+    a.borrow_mut().push(context.alloc(1).downgrade());
+    let garbage = context.alloc(String::from("garbage"));
+    drop(garbage);
+
+    fault.set(Some(FaultPoint::BeforeAlloc));
+    assert_eq!(context.try_alloc(2).unwrap_err().0, 2);
+    assert!(catch_unwind(AssertUnwindSafe(|| context.alloc(2))).is_err());
+
+    fault.set(Some(FaultPoint::DuringMark));
+    assert!(catch_unwind(AssertUnwindSafe(|| context.gc())).is_err());
+    assert_eq!(context.snapshot().len(), 2);
+    assert_eq!(*a.borrow()[0].upgrade().unwrap(), 1);
+
+    fault.set(Some(FaultPoint::DuringSweep));
+    assert!(catch_unwind(AssertUnwindSafe(|| context.gc())).is_err());
+    assert_eq!(context.snapshot().len(), 2);
+
+    context.clear_fault_injector();
+    drop(a);
+    context.gc();
+    assert!(context.snapshot().is_empty());
+}
+
+#[cfg(feature = "global")]
+#[test]
+fn test_global() {
+    let _ = env_logger::try_init();
+    struct Foo {
+        next: RefCell<Option<GcObject<'static, Foo>>>,
+    }
+    impl GcTarget<'static> for Foo {
+        fn trace(&self, token: &mut GcTraceToken<'static>) {
+            self.next.trace(token);
+        }
+    }
+
+    let a = global::gc_alloc(Foo {
+        next: RefCell::new(None),
+    });
+    let b = global::gc_alloc(Foo {
+        next: RefCell::new(Some(a.downgrade())),
+    });
+    *a.next.borrow_mut() = Some(b.downgrade());
+    drop(b);
+    global::gc_collect();
+    assert_eq!(global::context().snapshot().len(), 2);
+    drop(a);
+    global::gc_collect();
+    assert!(global::context().snapshot().is_empty());
+    assert!(
+        std::thread::spawn(|| global::context().snapshot().is_empty())
+            .join()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_value_eq() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(String::from("a"));
+    let b = context.alloc(String::from("a"));
+    assert!(a.value_eq(&b));
+    assert!(!a.ptr_eq(&b));
+    assert!(a.ptr_eq(&a.clone()));
+    assert!(!a.value_eq(&context.alloc(String::from("b"))));
+}
+
+#[test]
+fn test_reachable_from() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        next: RefCell<Vec<GcObjectThin<'c>>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let new = || {
+        context.alloc(Foo {
+            next: RefCell::new(Vec::new()),
+        })
+    };
+    let (a, b, c, d) = (new(), new(), new(), new());
+    a.next.borrow_mut().push(b.downgrade().cast_thin());
+    b.next.borrow_mut().push(c.downgrade().cast_thin());
+    c.next.borrow_mut().push(a.downgrade().cast_thin());
+    d.next.borrow_mut().push(a.downgrade().cast_thin());
+
+    let found = context.reachable_from(&b.downgrade().cast_thin());
+    let found = found.iter().map(|x| x.base_ptr()).collect::<Vec<_>>();
+    assert_eq!(found, [b.base_ptr(), c.base_ptr(), a.base_ptr()]);
+    assert_eq!(context.reachable_from(&d.downgrade().cast_thin()).len(), 4);
+
+    let e = context.alloc_cyclic_typed(|this| {
+        d.next.borrow_mut().push(this.clone().cast_thin());
+        assert_eq!(context.reachable_from(&d.downgrade().cast_thin()).len(), 4);
+        Foo {
+            next: RefCell::new(Vec::new()),
+        }
+    });
+    let found = context.reachable_from(&d.downgrade().cast_thin());
+    assert_eq!(found.len(), 5);
+    assert_eq!(found[2].base_ptr(), e.base_ptr());
+}
+
+#[test]
+fn test_weak_ref() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let list = context.alloc(RefCell::new(Vec::new()));
+    let a = context.alloc(String::from("a"));
+    let view = a.weak_ref();
+    let copy = view;
+    assert_eq!(&*copy, "a");
+    assert_eq!(copy.base_ptr(), a.base_ptr());
+    assert_eq!(a.downgrade().weak_count(), 1);
+    list.borrow_mut().push(view.to_object());
+    assert_eq!(view.to_root().downgrade().root_count(), 2);
+    drop(a);
+    context.gc();
+    assert_eq!(*list.borrow()[0].upgrade().unwrap(), "a");
+}
+
+#[test]
+fn test_try_gc() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        context: &'c GcContext<'c>,
+        this: RefCell<Option<GcObject<'c, Foo<'c>>>>,
+        nested: Rc<Cell<Option<bool>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.this.trace(token);
+        }
     }
 
     impl<'c> Drop for Foo<'c> {
         fn drop(&mut self) {
-            println!("Drop for Foo");
+            self.nested.set(Some(self.context.try_gc().is_some()));
+        }
+    }
+
+    let context = GcContext::new();
+    let nested = Rc::new(Cell::new(None));
+    let a = context.alloc(Foo {
+        context: &context,
+        this: RefCell::new(None),
+        nested: nested.clone(),
+    });
+    *a.this.borrow_mut() = Some(a.downgrade());
+    assert_eq!(context.try_gc().unwrap().held, 1);
+    drop(a);
+    assert_eq!(nested.get(), None);
+    assert_eq!(context.try_gc().unwrap().dropped, 1);
+    assert_eq!(nested.get(), Some(false));
+}
+
+#[cfg(feature = "background-free")]
+#[test]
+fn test_collect_background() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        next: RefCell<Option<GcObject<'c, Foo<'c>>>>,
+        drops: Rc<Cell<usize>>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+
+    impl<'c> Drop for Foo<'c> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    let drops = Rc::new(Cell::new(0));
+    let context = GcContext::new();
+    for _ in 0..2 {
+        for _ in 0..100 {
+            let a = context.alloc(Foo {
+                next: RefCell::new(None),
+                drops: drops.clone(),
+            });
+            *a.next.borrow_mut() = Some(a.downgrade());
+        }
+        let keep = context.alloc(Foo {
+            next: RefCell::new(None),
+            drops: drops.clone(),
+        });
+        assert_eq!(context.collect_background().unwrap().dropped, 100);
+        assert_eq!(context.snapshot().len(), 1);
+        drop(keep);
+    }
+    assert_eq!(drops.get(), 202);
+}
+
+#[test]
+fn test_vtable() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(1u32).cast_thin();
+    let b = context.alloc(2u32).cast_thin();
+    let c = context.alloc(String::new()).cast_thin();
+    assert_eq!(a.downgrade().vtable(), b.downgrade().vtable());
+    assert_ne!(a.downgrade().vtable(), c.downgrade().vtable());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_assert_collected() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let list = context.alloc(RefCell::new(Vec::new()));
+    let a = context.alloc(String::from("a"));
+    let weak = context.downgrade_weak(&a);
+    list.borrow_mut().push(a.downgrade());
+    drop(a);
+    let message = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        context.assert_collected(&weak)
+    }))
+    .unwrap_err();
+    let message = message.downcast_ref::<String>().unwrap();
+    assert!(message.starts_with("alloc::string::String at "));
+    assert!(message.contains("retained by Some(["));
+
+    list.borrow_mut().clear();
+    context.assert_collected(&weak);
+}
+
+#[test]
+fn test_cell_thin_slot() {
+    let _ = env_logger::try_init();
+
+    struct Node<'c> {
+        parent: Cell<Option<GcObjectThin<'c>>>,
+        children: RefCell<Vec<GcObject<'c, Node<'c>>>>,
+    }
+
+    impl<'c> GcTarget<'c> for Node<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            trace::trace_cell_take(&self.parent, token);
+            self.children.trace(token);
+        }
+    }
+
+    let context = GcContext::new();
+    let new = || {
+        context.alloc(Node {
+            parent: Cell::new(None),
+            children: RefCell::new(Vec::new()),
+        })
+    };
+    let root = new();
+    let child = new();
+    child.parent.set(Some(root.downgrade().cast_thin()));
+    root.children.borrow_mut().push(child.downgrade());
+    let root_ptr = root.base_ptr();
+    drop(root);
+    context.gc();
+    let parent = child.parent.take().unwrap();
+    assert_eq!(parent.base_ptr(), root_ptr);
+    assert!(parent.upgrade().is_some());
+    child.parent.set(Some(parent));
+    drop(child);
+    context.gc();
+    assert!(context.snapshot().is_empty());
+}
+
+#[test]
+fn test_retention_path_panic() {
+    let _ = env_logger::try_init();
+
+    struct Foo {
+        panic: Cell<bool>,
+    }
+
+    impl<'c> GcTarget<'c> for Foo {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            let _ = token;
+            assert!(!self.panic.get(), "trace panicked");
         }
     }
 
+    let context = GcContext::new();
+    let a = context.alloc(Foo {
+        panic: Cell::new(false),
+    });
+    let b = context.alloc(1);
+    context.gc();
+    let stats = context.stats();
+    assert_eq!(
+        context.retention_path(&b.downgrade().cast_thin()),
+        Some(vec![b.base_ptr().as_ptr() as usize])
+    );
+    a.panic.set(true);
+    let target = b.downgrade().cast_thin();
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        context.retention_path(&target)
+    }))
+    .is_err());
+    drop(target);
+    assert_eq!(
+        context.stats().estimated_live_bytes,
+        stats.estimated_live_bytes
+    );
+    a.panic.set(false);
+    assert_eq!(context.try_gc().unwrap().held, 2);
+}
+
+#[test]
+fn test_for_each_root_release() {
+    let _ = env_logger::try_init();
+
+    struct Foo<'c> {
+        next: RefCell<Option<GcObject<'c, u32>>>,
+    }
+
     impl<'c> GcTarget<'c> for Foo<'c> {
         fn trace(&self, token: &mut GcTraceToken<'c>) {
-            self.r.trace(token);
+            self.next.trace(token);
         }
     }
 
     let context = GcContext::new();
-    let x = context.alloc(Foo {
-        r: std::cell::RefCell::new(None),
+    let a = context.alloc(Foo {
+        next: RefCell::new(None),
     });
-    *x.r.borrow_mut() = Some(x.downgrade());
+    let b = context.alloc(1u32);
+    *a.next.borrow_mut() = Some(b.downgrade());
+    drop(b);
+    let mut a = Some(a);
+    let mut calls = 0;
+    // Releasing `a` while it is visited frees `b`, the object after it.
+    context.for_each_root(|_| {
+        calls += 1;
+        a.take();
+    });
+    assert_eq!(calls, 1);
+    assert!(context.snapshot().is_empty());
+}
+
+#[test]
+fn test_stress() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.set_stress(true);
+    let a = context.alloc(1);
+    let b = context.alloc(2);
+    assert_eq!(context.stats().allocs_since_gc, 1);
+    let pause = context.pause_gc();
+    let _c = context.alloc(3);
+    assert_eq!(context.stats().allocs_since_gc, 2);
+    drop(pause);
+    let _d = context.alloc(4);
+    assert_eq!(context.stats().allocs_since_gc, 1);
+    assert_eq!(*a + *b, 3);
+}
+
+#[test]
+fn test_root_in_collected_holder() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        this: RefCell<Option<GcObject<'c, Self>>>,
+        child: Option<GcRootThin<'c>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.this.trace(token);
+            self.child.trace(token);
+        }
+    }
+    let context = GcContext::new();
+    let child = context.alloc(Foo {
+        this: RefCell::new(None),
+        child: None,
+    });
+    *child.this.borrow_mut() = Some(child.downgrade());
+    let holder = context.alloc(Foo {
+        this: RefCell::new(None),
+        child: Some(child.cast_thin()),
+    });
+    *holder.this.borrow_mut() = Some(holder.downgrade());
+    drop(holder);
     context.gc();
-    *x.r.borrow_mut() = None;
+    assert_eq!(context.snapshot().len(), 1);
+    context.gc();
+    assert!(context.snapshot().is_empty());
+}
+
+#[test]
+fn test_clone_config() {
+    let _ = env_logger::try_init();
+    let template = GcContext::new();
+    template.set_auto_gc(1);
+    template.set_drop_order(DropOrder::OldestFirst);
+    template.set_trace_profiling(true);
+    let _a = template.alloc(1);
+    let context = template.clone_config();
+    assert!(context.snapshot().is_empty());
+    assert_eq!(context.inner().auto_gc.get(), 1);
+    assert_eq!(context.inner().drop_order.get(), DropOrder::OldestFirst);
+    let _b = context.alloc(String::new());
+    assert_eq!(context.last_trace_profile(), []);
+    let _c = context.alloc(2);
+    assert_eq!(context.last_trace_profile(), [("alloc::string::String", 1)]);
+}
+
+#[test]
+fn test_stress_hash_map_insert() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    context.set_stress(true);
+    let map = context.alloc(RefCell::new(HashMap::new()));
+    for i in 0..200u32 {
+        if i % 2 == 0 {
+            map.borrow_mut().insert(i, context.alloc(i).downgrade());
+        } else {
+            let value = context.alloc(i);
+            map.borrow_mut().insert(i, value.downgrade());
+        }
+    }
+    context.gc();
+    let map = map.borrow();
+    assert_eq!(map.len(), 200);
+    assert!(map.iter().all(|(k, v)| *v.upgrade().unwrap() == *k));
+}
+
+#[test]
+fn test_size_histogram() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let _a = context.alloc(1u8);
+    let _b = context.alloc([0u64; 8]);
+    let _c = context.alloc([1u64; 8]);
+    let histogram = context.size_histogram();
+    assert_eq!(histogram.len(), 2);
+    assert_eq!(histogram.values().copied().collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(
+        histogram.keys().last(),
+        Some(&size_of::<GcBox<'_, [u64; 8]>>())
+    );
+}
+
+#[test]
+fn test_try_into_root() {
+    let _ = env_logger::try_init();
+    let context = GcContext::new();
+    let a = context.alloc(1);
+    let obj = a.downgrade();
+    let root = obj.clone().try_into_root().unwrap();
+    assert_eq!(*root, 1);
+    assert_eq!(obj.root_count(), 2);
+}
+
+#[test]
+fn test_edges() {
+    let _ = env_logger::try_init();
+    struct Foo<'c> {
+        next: RefCell<Vec<GcObject<'c, Self>>>,
+    }
+    impl<'c> GcTarget<'c> for Foo<'c> {
+        fn trace(&self, token: &mut GcTraceToken<'c>) {
+            self.next.trace(token);
+        }
+    }
+    let context = GcContext::new();
+    let foo = || Foo {
+        next: RefCell::new(Vec::new()),
+    };
+    let a = context.alloc(foo());
+    let b = context.alloc(foo());
+    a.next.borrow_mut().push(b.downgrade());
+    a.next.borrow_mut().push(a.downgrade());
+    b.next.borrow_mut().push(a.downgrade());
+    let (a, b) = (a.ptr.as_ptr() as usize, b.ptr.as_ptr() as usize);
+    let mut edges = Vec::new();
+    context.edges(|from, to| edges.push((from, to)));
+    edges.sort_unstable();
+    let mut expected = vec![(a, b), (a, a), (b, a)];
+    expected.sort_unstable();
+    assert_eq!(edges, expected);
 }
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "alloc-tracking")]
+use std::panic::Location;
+
+/// The address and type name of every live object at one point in time.
+#[derive(Clone, Debug, Default)]
+pub struct HeapSnapshot {
+    objects: HashMap<usize, &'static str>,
+    #[cfg(feature = "alloc-tracking")]
+    locations: HashMap<usize, &'static Location<'static>>,
+}
+
+impl HeapSnapshot {
+    pub(crate) fn new(objects: HashMap<usize, &'static str>) -> Self {
+        Self {
+            objects,
+            #[cfg(feature = "alloc-tracking")]
+            locations: HashMap::new(),
+        }
+    }
+
+    #[cfg(feature = "alloc-tracking")]
+    pub(crate) fn with_locations(
+        mut self,
+        locations: HashMap<usize, &'static Location<'static>>,
+    ) -> Self {
+        self.locations = locations;
+        self
+    }
+
+    /// Returns where the object at `address` was allocated.
+    #[cfg(feature = "alloc-tracking")]
+    pub fn location(&self, address: usize) -> Option<&'static Location<'static>> {
+        self.locations.get(&address).copied()
+    }
+
+    /// Number of objects allocated at each site.
+    #[cfg(feature = "alloc-tracking")]
+    pub fn sites(&self) -> BTreeMap<&'static Location<'static>, usize> {
+        let mut sites = BTreeMap::new();
+        for &location in self.locations.values() {
+            *sites.entry(location).or_default() += 1;
+        }
+        sites
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.objects.contains_key(&address)
+    }
+
+    /// Returns the objects of `other` that are not in `self`, grouped by type name.
+    ///
+    /// Addresses may be reused after an object is freed, so a replaced object with the same
+    /// address and type is not reported.
+    pub fn diff(&self, other: &HeapSnapshot) -> HeapDiff {
+        let mut added = BTreeMap::<&'static str, Vec<usize>>::new();
+        for (&address, &name) in &other.objects {
+            if self.objects.get(&address) != Some(&name) {
+                added.entry(name).or_default().push(address);
+            }
+        }
+        for addresses in added.values_mut() {
+            addresses.sort_unstable();
+        }
+        HeapDiff { added }
+    }
+}
+
+/// Objects present in a later snapshot but not an earlier one.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HeapDiff {
+    added: BTreeMap<&'static str, Vec<usize>>,
+}
+
+impl HeapDiff {
+    /// Addresses of the added objects, grouped by type name.
+    pub fn added(&self) -> &BTreeMap<&'static str, Vec<usize>> {
+        &self.added
+    }
+
+    /// Number of added objects of each type.
+    pub fn counts(&self) -> BTreeMap<&'static str, usize> {
+        self.added
+            .iter()
+            .map(|(&name, addresses)| (name, addresses.len()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.added.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+    }
+}
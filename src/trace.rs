@@ -1,14 +1,23 @@
-use std::any::TypeId;
+use std::any::{type_name, TypeId};
+use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
-use std::collections::{LinkedList, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, LinkedList, VecDeque};
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs::File;
+use std::hash::BuildHasher;
 use std::marker::{PhantomData, PhantomPinned};
-use std::ops::Deref;
+use std::mem::ManuallyDrop;
+use std::num::{Saturating, Wrapping};
+use std::ops::{ControlFlow, Deref};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
 use std::time::{Duration, Instant};
 
+use log::warn;
+
 use crate::{trace_none, GcTarget, GcTraceToken};
 
 impl<'c, T: GcTarget<'c> + ?Sized> GcTarget<'c> for &T {
@@ -31,6 +40,14 @@ impl<'c, T: GcTarget<'c>> GcTarget<'c> for [T] {
     }
 }
 
+impl<'c, T: GcTarget<'c>, const N: usize> GcTarget<'c> for [T; N] {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        for i in self {
+            i.trace(token);
+        }
+    }
+}
+
 impl<'c, T: GcTarget<'c>> GcTarget<'c> for Vec<T> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
         for i in self {
@@ -55,6 +72,35 @@ impl<'c, T: GcTarget<'c>> GcTarget<'c> for VecDeque<T> {
     }
 }
 
+/// A map is only traced through a shared reference, so a collection never observes an insert
+/// in progress. Allocating while a `RefCell` holding the map is mutably borrowed makes that
+/// collection incomplete instead.
+impl<'c, K: GcTarget<'c>, V: GcTarget<'c>, S: BuildHasher> GcTarget<'c> for HashMap<K, V, S> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        for (k, v) in self {
+            k.trace(token);
+            v.trace(token);
+        }
+    }
+}
+
+impl<'c, K: GcTarget<'c>, V: GcTarget<'c>> GcTarget<'c> for BTreeMap<K, V> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        for (k, v) in self {
+            k.trace(token);
+            v.trace(token);
+        }
+    }
+}
+
+impl<'c, T: GcTarget<'c>> GcTarget<'c> for BTreeSet<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        for i in self {
+            i.trace(token);
+        }
+    }
+}
+
 impl<'c, T: GcTarget<'c>> GcTarget<'c> for Option<T> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
         if let Some(x) = self {
@@ -72,27 +118,162 @@ impl<'c, T: GcTarget<'c>, E: GcTarget<'c>> GcTarget<'c> for Result<T, E> {
     }
 }
 
+impl<'c, B: GcTarget<'c>, C: GcTarget<'c>> GcTarget<'c> for ControlFlow<B, C> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        match self {
+            ControlFlow::Continue(x) => x.trace(token),
+            ControlFlow::Break(x) => x.trace(token),
+        }
+    }
+}
+
+#[cfg(feature = "either")]
+impl<'c, L: GcTarget<'c>, R: GcTarget<'c>> GcTarget<'c> for either::Either<L, R> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        match self {
+            either::Either::Left(x) => x.trace(token),
+            either::Either::Right(x) => x.trace(token),
+        }
+    }
+}
+
+/// Traces the pointee recursively, so a list linked by `Option<Box<Node>>` uses one stack
+/// frame per node and a long one can overflow the stack. Only `GcObject` edges go through the
+/// worklist; link deep structures through a `GcObject` at least every few thousand nodes.
 impl<'c, T: GcTarget<'c> + ?Sized> GcTarget<'c> for Box<T> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
-        T::trace(self, token);
+        token.trace_nested::<T>(self);
     }
 }
 
+/// Traces the pointee the first time it is reached during a trace, so a subtree shared by
+/// several `Rc`s is traced once, and a cycle of `Rc`s terminates.
 impl<'c, T: GcTarget<'c> + ?Sized> GcTarget<'c> for Rc<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        if token.visit_shared(Rc::as_ptr(self).cast()) {
+            token.trace_nested::<T>(self);
+        }
+    }
+}
+
+/// Like `Rc`, the pointee is traced once per trace.
+impl<'c, T: GcTarget<'c> + ?Sized> GcTarget<'c> for Arc<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        if token.visit_shared(Arc::as_ptr(self).cast()) {
+            token.trace_nested::<T>(self);
+        }
+    }
+}
+
+impl<'c, B: GcTarget<'c> + ToOwned + ?Sized> GcTarget<'c> for Cow<'_, B>
+where
+    B::Owned: GcTarget<'c>,
+{
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        match self {
+            Cow::Borrowed(x) => x.trace(token),
+            Cow::Owned(x) => x.trace(token),
+        }
+    }
+}
+
+impl<'c, T: GcTarget<'c>> GcTarget<'c> for ManuallyDrop<T> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
         T::trace(self, token);
     }
 }
 
+impl<'c, T: GcTarget<'c>> GcTarget<'c> for Reverse<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        self.0.trace(token);
+    }
+}
+
+impl<'c, T: GcTarget<'c>> GcTarget<'c> for Wrapping<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        self.0.trace(token);
+    }
+}
+
+impl<'c, T: GcTarget<'c>> GcTarget<'c> for Saturating<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        self.0.trace(token);
+    }
+}
+
 impl<'c, T: GcTarget<'c> + Copy> GcTarget<'c> for Cell<T> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
         self.get().trace(token);
     }
 }
 
+/// Traces a `Cell` of a value that is not `Copy`, like `Cell<Option<GcObjectThin>>`, by taking
+/// the value out while it is traced.
+///
+/// `Cell<T>` only implements `GcTarget` for `T: Copy`, and a second impl would overlap with it.
+/// Use this from a manual `trace`, or with `#[trace(with = "regc::trace::trace_cell_take")]`.
+pub fn trace_cell_take<'c, T: GcTarget<'c> + Default>(
+    cell: &Cell<T>,
+    token: &mut GcTraceToken<'c>,
+) {
+    let value = cell.take();
+    value.trace(token);
+    cell.set(value);
+}
+
 impl<'c, T: GcTarget<'c> + ?Sized> GcTarget<'c> for RefCell<T> {
     fn trace(&self, token: &mut GcTraceToken<'c>) {
-        T::trace(self.borrow().deref(), token);
+        match self.try_borrow() {
+            Ok(r) => T::trace(r.deref(), token),
+            Err(_) => {
+                warn!("skip trace of mutably borrowed {}", type_name::<Self>());
+                token.set_incomplete();
+            }
+        }
+    }
+}
+
+/// A mutex locked during the trace, even by the collecting thread, marks the collection
+/// incomplete so that nothing is freed, since its contents can not be inspected. The contents
+/// of a poisoned mutex are traced as usual.
+impl<'c, T: GcTarget<'c> + ?Sized> GcTarget<'c> for Mutex<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        match self.try_lock() {
+            Ok(r) => T::trace(r.deref(), token),
+            Err(TryLockError::Poisoned(e)) => T::trace(e.into_inner().deref(), token),
+            Err(TryLockError::WouldBlock) => {
+                warn!("skip trace of locked {}", type_name::<Self>());
+                token.set_incomplete();
+            }
+        }
+    }
+}
+
+/// Like the `Mutex` impl, a write locked `RwLock` makes the collection free nothing.
+impl<'c, T: GcTarget<'c> + ?Sized> GcTarget<'c> for RwLock<T> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        match self.try_read() {
+            Ok(r) => T::trace(r.deref(), token),
+            Err(TryLockError::Poisoned(e)) => T::trace(e.into_inner().deref(), token),
+            Err(TryLockError::WouldBlock) => {
+                warn!("skip trace of locked {}", type_name::<Self>());
+                token.set_incomplete();
+            }
+        }
+    }
+}
+
+/// Raw pointers are never followed, an object only reachable through one is not kept alive.
+impl<'c, T: ?Sized> GcTarget<'c> for *const T {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        let _ = token;
+    }
+}
+
+/// Like `*const T`, the pointee is not traced.
+impl<'c, T: ?Sized> GcTarget<'c> for *mut T {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        let _ = token;
     }
 }
 
@@ -102,33 +283,46 @@ impl<'c, T> GcTarget<'c> for PhantomData<T> {
     }
 }
 
-trace_none!(PhantomPinned);
-
-trace_none!(bool);
-trace_none!(i8);
-trace_none!(u8);
-trace_none!(i16);
-trace_none!(u16);
-trace_none!(i32);
-trace_none!(u32);
-trace_none!(i64);
-trace_none!(u64);
-trace_none!(i128);
-trace_none!(u128);
-trace_none!(isize);
-trace_none!(usize);
-trace_none!(f32);
-trace_none!(f64);
+trace_none!(@any PhantomPinned);
+
+trace_none!(@any bool);
+trace_none!(@any i8);
+trace_none!(@any u8);
+trace_none!(@any i16);
+trace_none!(@any u16);
+trace_none!(@any i32);
+trace_none!(@any u32);
+trace_none!(@any i64);
+trace_none!(@any u64);
+trace_none!(@any i128);
+trace_none!(@any u128);
+trace_none!(@any isize);
+trace_none!(@any usize);
+trace_none!(@any f32);
+trace_none!(@any f64);
 trace_none!(str);
-trace_none!(String);
+trace_none!(@any String);
 trace_none!(OsStr);
-trace_none!(OsString);
+trace_none!(@any OsString);
 trace_none!(Path);
-trace_none!(PathBuf);
-trace_none!(TypeId);
-trace_none!(File);
-trace_none!(Instant);
-trace_none!(Duration);
+trace_none!(@any PathBuf);
+trace_none!(@any TypeId);
+trace_none!(@any File);
+trace_none!(@any Instant);
+trace_none!(@any Duration);
+trace_none!(@any fmt::Error);
+trace_none!(@any fmt::Alignment);
+
+#[cfg(feature = "bytes")]
+trace_none!(@any bytes::Bytes);
+#[cfg(feature = "bytes")]
+trace_none!(@any bytes::BytesMut);
+#[cfg(feature = "smol_str")]
+trace_none!(@any smol_str::SmolStr);
+#[cfg(feature = "compact_str")]
+trace_none!(@any compact_str::CompactString);
+#[cfg(feature = "ecow")]
+trace_none!(@any ecow::EcoString);
 
 macro_rules! trace_tuple {
     ($($name:ident)*) => {
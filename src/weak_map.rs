@@ -0,0 +1,239 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::{GcContext, GcRoot, GcTarget, GcTraceToken, GcWeak};
+
+/// Entries below which insertion never prunes.
+const MIN_PRUNE: usize = 8;
+
+/// A map with weak keys, entries are dropped once their key is collected.
+///
+/// Keys are compared by identity. Dead entries are pruned by [`WeakKeyMap::prune`], after
+/// every collection once registered with [`WeakKeyMap::prune_after_gc`], and on insertion
+/// once the map doubled since the last prune. Values are traced until their entry is pruned,
+/// so a value that refers to its own key keeps the key alive.
+pub struct WeakKeyMap<'c, K: GcTarget<'c> + ?Sized + 'c, V> {
+    entries: HashMap<*const (), (GcWeak<'c, K>, V)>,
+    prune_at: usize,
+}
+
+impl<'c, K: GcTarget<'c> + ?Sized + 'c, V> WeakKeyMap<'c, K, V> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            prune_at: MIN_PRUNE,
+        }
+    }
+
+    pub fn insert(&mut self, key: GcWeak<'c, K>, value: V) -> Option<V> {
+        if self.entries.len() >= self.prune_at {
+            self.prune();
+        }
+        if !key.is_live() {
+            return None;
+        }
+        // a dead entry may share the address of the new key
+        self.entries
+            .insert(key.ptr.as_ptr() as *const (), (key, value))
+            .and_then(|(old, value)| old.is_live().then_some(value))
+    }
+
+    pub fn get(&self, key: &GcRoot<'c, K>) -> Option<&V> {
+        match self.entries.get(&(key.ptr.as_ptr() as *const ())) {
+            Some((weak, value)) if weak.is_live() => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &GcRoot<'c, K>) -> Option<&mut V> {
+        match self.entries.get_mut(&(key.ptr.as_ptr() as *const ())) {
+            Some((weak, value)) if weak.is_live() => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &GcRoot<'c, K>) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &GcRoot<'c, K>) -> Option<V> {
+        let ptr = key.ptr.as_ptr() as *const ();
+        match self.entries.get(&ptr) {
+            Some((weak, _)) if weak.is_live() => self.entries.remove(&ptr).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Drops every entry whose key was collected.
+    pub fn prune(&mut self) {
+        self.entries.retain(|_, (key, _)| key.is_live());
+        self.prune_at = MIN_PRUNE.max(self.entries.len() * 2);
+    }
+
+    /// Prunes `map` after every collection of `context`, until the map is dropped.
+    ///
+    /// A collection that ends while the map is borrowed leaves it to the next one.
+    pub fn prune_after_gc(map: &Rc<RefCell<Self>>, context: &GcContext<'c>)
+    where
+        V: 'c,
+    {
+        context.add_post_gc_hook(prune_hook(map, Self::prune));
+    }
+
+    /// Returns the number of entries with a live key.
+    pub fn len(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|(key, _)| key.is_live())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> + use<'_, 'c, K, V> {
+        self.entries
+            .values()
+            .filter(|(key, _)| key.is_live())
+            .map(|(_, value)| value)
+    }
+}
+
+impl<'c, K: GcTarget<'c> + ?Sized + 'c, V> Default for WeakKeyMap<'c, K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'c, K: GcTarget<'c> + ?Sized + 'c, V: GcTarget<'c>> GcTarget<'c> for WeakKeyMap<'c, K, V> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        for (_, value) in self.entries.values() {
+            value.trace(token);
+        }
+    }
+}
+
+fn prune_hook<'c, T: 'c>(map: &Rc<RefCell<T>>, prune: fn(&mut T)) -> Box<dyn FnMut() -> bool + 'c> {
+    let map = Rc::downgrade(map);
+    Box::new(move || {
+        let Some(map) = map.upgrade() else {
+            return false;
+        };
+        if let Ok(mut map) = map.try_borrow_mut() {
+            prune(&mut map);
+        }
+        true
+    })
+}
+
+/// A map with weak values, entries are dropped once their value is collected.
+///
+/// Only keys are traced. Dead entries are pruned like in [`WeakKeyMap`], including after every
+/// collection once registered with [`WeakValueMap::prune_after_gc`].
+pub struct WeakValueMap<'c, K, V: GcTarget<'c> + ?Sized + 'c> {
+    entries: HashMap<K, GcWeak<'c, V>>,
+    prune_at: usize,
+}
+
+impl<'c, K: Eq + Hash, V: GcTarget<'c> + ?Sized + 'c> WeakValueMap<'c, K, V> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            prune_at: MIN_PRUNE,
+        }
+    }
+
+    /// Inserts `value`, returning the previous value if it is still alive.
+    pub fn insert(&mut self, key: K, value: GcWeak<'c, V>) -> Option<GcRoot<'c, V>> {
+        if self.entries.len() >= self.prune_at {
+            self.prune();
+        }
+        self.entries.insert(key, value)?.upgrade()
+    }
+
+    #[must_use = "dropping this root makes the object eligible for collection"]
+    pub fn get<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> Option<GcRoot<'c, V>>
+    where
+        K: Borrow<Q>,
+    {
+        self.entries.get(key)?.upgrade()
+    }
+
+    pub fn contains_key<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.entries.get(key).is_some_and(GcWeak::is_live)
+    }
+
+    pub fn remove<Q: Eq + Hash + ?Sized>(&mut self, key: &Q) -> Option<GcRoot<'c, V>>
+    where
+        K: Borrow<Q>,
+    {
+        self.entries.remove(key)?.upgrade()
+    }
+
+    /// Drops every entry whose value was collected.
+    pub fn prune(&mut self) {
+        self.entries.retain(|_, value| value.is_live());
+        self.prune_at = MIN_PRUNE.max(self.entries.len() * 2);
+    }
+
+    /// Prunes `map` after every collection of `context`, until the map is dropped.
+    pub fn prune_after_gc(map: &Rc<RefCell<Self>>, context: &GcContext<'c>)
+    where
+        K: 'c,
+    {
+        context.add_post_gc_hook(prune_hook(map, Self::prune));
+    }
+
+    /// Returns the number of entries with a live value.
+    pub fn len(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|value| value.is_live())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'c, K: Eq + Hash, V: GcTarget<'c> + ?Sized + 'c> Default for WeakValueMap<'c, K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'c, K: GcTarget<'c>, V: GcTarget<'c> + ?Sized + 'c> GcTarget<'c> for WeakValueMap<'c, K, V> {
+    fn trace(&self, token: &mut GcTraceToken<'c>) {
+        for key in self.entries.keys() {
+            key.trace(token);
+        }
+    }
+}
+
+/// Removes the weak handles whose target was collected.
+///
+/// A `Vec<GcWeak>` is traced without keeping its targets alive, so an observer list can hold
+/// stale entries until it is pruned.
+pub trait PruneDead {
+    fn prune_dead(&mut self);
+}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> PruneDead for Vec<GcWeak<'c, T>> {
+    fn prune_dead(&mut self) {
+        self.retain(GcWeak::is_live);
+    }
+}
+
+impl<'c, T: GcTarget<'c> + ?Sized + 'c> PruneDead for VecDeque<GcWeak<'c, T>> {
+    fn prune_dead(&mut self) {
+        self.retain(GcWeak::is_live);
+    }
+}
@@ -0,0 +1,10 @@
+#![deny(unused_must_use)]
+
+use regc::GcContext;
+
+fn main() {
+    let context = GcContext::new();
+    context.alloc(1);
+    let x = context.alloc(2);
+    x.downgrade().upgrade();
+}